@@ -0,0 +1,74 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+use storedb::{Collection, Database};
+
+const ENTRIES: u64 = 10_000;
+
+fn setup() -> (Database, Collection<u64, String>) {
+  let db = Database::temporary().unwrap();
+  let coll = db.get_collection::<u64, String>("bench").unwrap();
+  coll.transaction(|tx| tx.set_many((0..ENTRIES).map(|i| (i, format!("value-{i}"))))).unwrap();
+  (db, coll)
+}
+
+fn point_reads(c: &mut Criterion) {
+  let (_db, coll) = setup();
+  let mut group = c.benchmark_group("point_reads");
+  let tx = coll.read().unwrap();
+  let mut key = 0;
+  group.bench_function("get", |b| b.iter(|| {
+    key = (key + 7919) % ENTRIES;
+    black_box(tx.get(key).unwrap())
+  }));
+  group.bench_function("contains", |b| b.iter(|| {
+    key = (key + 7919) % ENTRIES;
+    black_box(tx.contains(key).unwrap())
+  }));
+  drop(tx);
+  group.bench_function("get_in_own_transaction", |b| b.iter(|| {
+    key = (key + 7919) % ENTRIES;
+    black_box(coll.read().unwrap().get(key).unwrap())
+  }));
+  group.finish();
+}
+
+fn point_writes(c: &mut Criterion) {
+  let (_db, coll) = setup();
+  let mut group = c.benchmark_group("point_writes");
+  let mut key = 0;
+  {
+    let mut tx = coll.begin().unwrap();
+    group.bench_function("set", |b| b.iter(|| {
+      key = (key + 7919) % ENTRIES;
+      tx.set(key, "updated").unwrap()
+    }));
+    tx.rollback().unwrap();
+  }
+  group.bench_function("set_in_own_transaction", |b| b.iter(|| {
+    key = (key + 7919) % ENTRIES;
+    coll.transaction(|tx| tx.set(key, "updated")).unwrap()
+  }));
+  group.finish();
+}
+
+/// The statement behind `get`, parsed on every call versus taken from the
+/// connection's statement cache.
+fn statement_cache(c: &mut Criterion) {
+  let (db, _coll) = setup();
+  let conn = rusqlite::Connection::open(db.path().unwrap()).unwrap();
+  let sql = "SELECT value FROM kv_store WHERE collection = ? AND key = ?";
+  let key = [0u8; 9];
+  let mut group = c.benchmark_group("statement");
+  group.bench_function("prepare", |b| b.iter(|| {
+    let mut stmt = conn.prepare(sql).unwrap();
+    black_box(stmt.exists(rusqlite::params!["bench", &key[..]]).unwrap())
+  }));
+  group.bench_function("prepare_cached", |b| b.iter(|| {
+    let mut stmt = conn.prepare_cached(sql).unwrap();
+    black_box(stmt.exists(rusqlite::params!["bench", &key[..]]).unwrap())
+  }));
+  group.finish();
+}
+
+criterion_group!(benches, point_reads, point_writes, statement_cache);
+criterion_main!(benches);
@@ -11,8 +11,8 @@ struct User {
 fn main() -> Result<(), Error> {
   let _ = fs::remove_file("example.db");
 
-  let mut db = Database::new("example.db")?;
-  let mut users = db.get_collection::<u32, User>("users")?;
+  let db = Database::new("example.db")?;
+  let users = db.get_collection::<u32, User>("users")?;
 
   {
     let mut tx = users.begin()?;
@@ -0,0 +1,362 @@
+//! Async wrappers around the blocking API, enabled with the `async` feature.
+//!
+//! One-shot operations run on tokio's blocking thread pool. Transactions keep a
+//! SQLite transaction open across `.await` points, so each one is driven by a
+//! dedicated thread that owns it and executes the calls sent to it.
+
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::sync::oneshot;
+use tokio::task;
+use crate::{Collection, CollectionTx, Database, DatabaseTx, Error, ReadOnly, ReadWrite};
+
+/// Async handle to a `Database`.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+  db: Database,
+}
+
+impl AsyncDatabase {
+  pub async fn new<P: AsRef<Path>>(db_path: P) -> Result<Self, Error> {
+    let db_path = db_path.as_ref().to_path_buf();
+    let db = spawn_blocking(move || Database::new(db_path)).await?;
+    Ok(AsyncDatabase { db })
+  }
+
+  /// Returns the underlying blocking handle.
+  pub fn blocking(&self) -> &Database {
+    &self.db
+  }
+
+  pub async fn get_collection<K, V>(&self, name: &str) -> Result<AsyncCollection<K, V>, Error>
+  where
+    K: Eq + Serialize + DeserializeOwned + Send + 'static,
+    V: Serialize + DeserializeOwned + Send + 'static,
+  {
+    let db = self.db.clone();
+    let name = name.to_string();
+    let inner = spawn_blocking(move || db.get_collection::<K, V>(&name)).await?;
+    Ok(AsyncCollection { inner })
+  }
+
+  /// Starts a database-wide transaction, waiting for other writers to finish.
+  pub async fn begin(&self) -> Result<AsyncDatabaseTx, Error> {
+    let worker = Worker::<DatabaseTx>::spawn(self.db.clone(), |db| db.begin()).await?;
+    Ok(AsyncDatabaseTx { worker })
+  }
+
+  /// Async counterpart of `Database::transaction`.
+  pub async fn transaction<T, F>(&self, f: F) -> Result<T, Error>
+  where
+    T: Send + 'static,
+    F: FnMut(&DatabaseTx<'_>) -> Result<T, Error> + Send + 'static,
+  {
+    let db = self.db.clone();
+    spawn_blocking(move || db.transaction(f)).await
+  }
+}
+
+impl From<Database> for AsyncDatabase {
+  fn from(db: Database) -> Self {
+    AsyncDatabase { db }
+  }
+}
+
+/// Async counterpart of `DatabaseTx`. Dropping it without committing rolls back.
+pub struct AsyncDatabaseTx {
+  worker: Worker<DatabaseTx<'static>>,
+}
+
+impl AsyncDatabaseTx {
+  /// Runs `f` against the underlying blocking transaction, for instance to
+  /// work with its collections through `DatabaseTx::collection`.
+  pub async fn run<T, F>(&mut self, f: F) -> Result<T, Error>
+  where
+    T: Send + 'static,
+    F: FnOnce(&DatabaseTx<'_>) -> Result<T, Error> + Send + 'static,
+  {
+    self.worker.call(move |tx| f(tx)).await
+  }
+
+  pub async fn cancel(self) -> Result<(), Error> {
+    self.rollback().await
+  }
+
+  pub async fn rollback(self) -> Result<(), Error> {
+    self.worker.finish(|tx| tx.rollback()).await
+  }
+
+  pub async fn commit(self) -> Result<(), Error> {
+    self.worker.finish(|tx| tx.commit()).await
+  }
+}
+
+/// Async handle to a `Collection`.
+pub struct AsyncCollection<K, V> {
+  inner: Collection<K, V>,
+}
+
+impl<K, V> AsyncCollection<K, V>
+where
+  K: Eq + Serialize + DeserializeOwned + Send + 'static,
+  V: Serialize + DeserializeOwned + Send + 'static,
+{
+  /// Returns the underlying blocking handle.
+  pub fn blocking(&self) -> &Collection<K, V> {
+    &self.inner
+  }
+
+  /// Starts a read-write transaction, waiting for other writers to finish.
+  pub async fn begin(&self) -> Result<AsyncCollectionTx<K, V>, Error> {
+    let worker = Worker::<CollectionTx<K, V>>::spawn(self.inner.clone(), |coll| coll.begin()).await?;
+    Ok(AsyncCollectionTx { worker })
+  }
+
+  /// Starts a read-only transaction, see `Collection::read`.
+  pub async fn read(&self) -> Result<AsyncReadTx<K, V>, Error> {
+    let worker = Worker::<CollectionTx<K, V, ReadOnly>>::spawn(self.inner.clone(), |coll| coll.read()).await?;
+    Ok(AsyncReadTx { worker })
+  }
+
+  /// Async counterpart of `Collection::transaction`.
+  pub async fn transaction<T, F>(&self, f: F) -> Result<T, Error>
+  where
+    T: Send + 'static,
+    F: FnMut(&mut CollectionTx<'_, K, V>) -> Result<T, Error> + Send + 'static,
+  {
+    let inner = self.inner.clone();
+    spawn_blocking(move || inner.transaction(f)).await
+  }
+}
+
+impl<K, V> Clone for AsyncCollection<K, V> {
+  fn clone(&self) -> Self {
+    AsyncCollection { inner: self.inner.clone() }
+  }
+}
+
+impl<K, V> From<Collection<K, V>> for AsyncCollection<K, V> {
+  fn from(inner: Collection<K, V>) -> Self {
+    AsyncCollection { inner }
+  }
+}
+
+/// Async counterpart of `CollectionTx`. Dropping it without committing rolls back.
+pub struct AsyncCollectionTx<K, V> {
+  worker: Worker<CollectionTx<'static, K, V, ReadWrite>>,
+}
+
+impl<K, V> AsyncCollectionTx<K, V>
+where
+  K: Eq + Serialize + DeserializeOwned + Send + 'static,
+  V: Serialize + DeserializeOwned + Send + 'static,
+{
+  pub async fn contains<Q: Into<K>>(&self, key: Q) -> Result<bool, Error> {
+    let key = key.into();
+    self.worker.call(move |tx| tx.contains(key)).await
+  }
+
+  pub async fn get<Q: Into<K>>(&self, key: Q) -> Result<Option<V>, Error> {
+    let key = key.into();
+    self.worker.call(move |tx| tx.get(key)).await
+  }
+
+  pub async fn set<Q: Into<K>, W: Into<V>>(&mut self, key: Q, val: W) -> Result<(), Error> {
+    let (key, val) = (key.into(), val.into());
+    self.worker.call(move |tx| tx.set(key, val)).await
+  }
+
+  pub async fn put<Q: Into<K>, W: Into<V>>(&mut self, key: Q, val: W) -> Result<(), Error> {
+    let (key, val) = (key.into(), val.into());
+    self.worker.call(move |tx| tx.put(key, val)).await
+  }
+
+  pub async fn del<Q: Into<K>>(&mut self, key: Q) -> Result<(), Error> {
+    let key = key.into();
+    self.worker.call(move |tx| tx.del(key)).await
+  }
+
+  pub async fn keys(&self) -> Result<Vec<K>, Error> {
+    self.worker.call(|tx| tx.keys()).await
+  }
+
+  pub async fn scan(&self) -> Result<Vec<(K, V)>, Error> {
+    self.worker.call(|tx| tx.scan()).await
+  }
+
+  pub async fn clear(&mut self) -> Result<(), Error> {
+    self.worker.call(|tx| tx.clear()).await
+  }
+
+  pub async fn count(&self) -> Result<usize, Error> {
+    self.worker.call(|tx| tx.count()).await
+  }
+
+  /// Runs `f` against the underlying blocking transaction, for operations that
+  /// have no async wrapper.
+  pub async fn run<T, F>(&mut self, f: F) -> Result<T, Error>
+  where
+    T: Send + 'static,
+    F: FnOnce(&mut CollectionTx<'_, K, V>) -> Result<T, Error> + Send + 'static,
+  {
+    self.worker.call(f).await
+  }
+
+  pub async fn cancel(self) -> Result<(), Error> {
+    self.rollback().await
+  }
+
+  pub async fn rollback(self) -> Result<(), Error> {
+    self.worker.finish(|tx| tx.rollback()).await
+  }
+
+  pub async fn commit(self) -> Result<(), Error> {
+    self.worker.finish(|tx| tx.commit()).await
+  }
+}
+
+/// Async counterpart of `ReadTx`.
+pub struct AsyncReadTx<K, V> {
+  worker: Worker<CollectionTx<'static, K, V, ReadOnly>>,
+}
+
+impl<K, V> AsyncReadTx<K, V>
+where
+  K: Eq + Serialize + DeserializeOwned + Send + 'static,
+  V: Serialize + DeserializeOwned + Send + 'static,
+{
+  pub async fn contains<Q: Into<K>>(&self, key: Q) -> Result<bool, Error> {
+    let key = key.into();
+    self.worker.call(move |tx| tx.contains(key)).await
+  }
+
+  pub async fn get<Q: Into<K>>(&self, key: Q) -> Result<Option<V>, Error> {
+    let key = key.into();
+    self.worker.call(move |tx| tx.get(key)).await
+  }
+
+  pub async fn keys(&self) -> Result<Vec<K>, Error> {
+    self.worker.call(|tx| tx.keys()).await
+  }
+
+  pub async fn scan(&self) -> Result<Vec<(K, V)>, Error> {
+    self.worker.call(|tx| tx.scan()).await
+  }
+
+  pub async fn count(&self) -> Result<usize, Error> {
+    self.worker.call(|tx| tx.count()).await
+  }
+
+  /// Runs `f` against the underlying blocking transaction, for operations that
+  /// have no async wrapper.
+  pub async fn run<T, F>(&self, f: F) -> Result<T, Error>
+  where
+    T: Send + 'static,
+    F: FnOnce(&mut CollectionTx<'_, K, V, ReadOnly>) -> Result<T, Error> + Send + 'static,
+  {
+    self.worker.call(f).await
+  }
+}
+
+/// A blocking transaction that a `Worker` can drive: `Tx<'s>` is the
+/// transaction borrowing from the handle `Source` it was started on.
+trait Transaction {
+  type Source;
+  type Tx<'s>;
+}
+
+impl<K, V, M> Transaction for CollectionTx<'static, K, V, M> {
+  type Source = Collection<K, V>;
+  type Tx<'s> = CollectionTx<'s, K, V, M>;
+}
+
+impl Transaction for DatabaseTx<'static> {
+  type Source = Database;
+  type Tx<'s> = DatabaseTx<'s>;
+}
+
+type Call<T> = Box<dyn for<'t> FnOnce(&mut <T as Transaction>::Tx<'t>) + Send>;
+type Finish<T> = Box<dyn for<'t> FnOnce(<T as Transaction>::Tx<'t>) + Send>;
+
+enum Command<T: Transaction> {
+  Call(Call<T>),
+  Finish(Finish<T>),
+}
+
+/// Thread owning one blocking transaction. Dropping the sender ends the thread,
+/// which drops (and so rolls back) the transaction if it is still open.
+struct Worker<T: Transaction> {
+  commands: mpsc::Sender<Command<T>>,
+}
+
+impl<T> Worker<T>
+where
+  T: Transaction + 'static,
+  T::Source: Send + 'static,
+{
+  async fn spawn<B>(source: T::Source, begin: B) -> Result<Self, Error>
+  where
+    B: for<'s> FnOnce(&'s T::Source) -> Result<T::Tx<'s>, Error> + Send + 'static,
+  {
+    let (commands, rx) = mpsc::channel::<Command<T>>();
+    let (ready_tx, ready_rx) = oneshot::channel();
+    thread::Builder::new()
+      .name("storedb-tx".into())
+      .spawn(move || {
+        let mut tx = match begin(&source) {
+          Ok(tx) => tx,
+          Err(e) => {
+            let _ = ready_tx.send(Err(e));
+            return;
+          }
+        };
+        if ready_tx.send(Ok(())).is_err() {
+          return;
+        }
+        while let Ok(command) = rx.recv() {
+          match command {
+            Command::Call(f) => f(&mut tx),
+            Command::Finish(f) => return f(tx),
+          }
+        }
+      })
+      .map_err(|_| Error::WorkerStopped)?;
+    ready_rx.await.map_err(|_| Error::WorkerStopped)??;
+    Ok(Worker { commands })
+  }
+
+  async fn call<R, F>(&self, f: F) -> Result<R, Error>
+  where
+    R: Send + 'static,
+    F: for<'t> FnOnce(&mut T::Tx<'t>) -> Result<R, Error> + Send + 'static,
+  {
+    let (reply, result) = oneshot::channel();
+    let call: Call<T> = Box::new(move |tx| {
+      let _ = reply.send(f(tx));
+    });
+    self.commands.send(Command::Call(call)).map_err(|_| Error::WorkerStopped)?;
+    result.await.map_err(|_| Error::WorkerStopped)?
+  }
+
+  async fn finish<F>(self, f: F) -> Result<(), Error>
+  where
+    F: for<'t> FnOnce(T::Tx<'t>) -> Result<(), Error> + Send + 'static,
+  {
+    let (reply, result) = oneshot::channel();
+    let finish: Finish<T> = Box::new(move |tx| {
+      let _ = reply.send(f(tx));
+    });
+    self.commands.send(Command::Finish(finish)).map_err(|_| Error::WorkerStopped)?;
+    result.await.map_err(|_| Error::WorkerStopped)?
+  }
+}
+
+async fn spawn_blocking<T, F>(f: F) -> Result<T, Error>
+where
+  T: Send + 'static,
+  F: FnOnce() -> Result<T, Error> + Send + 'static,
+{
+  task::spawn_blocking(f).await.map_err(|_| Error::WorkerStopped)?
+}
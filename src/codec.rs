@@ -0,0 +1,194 @@
+//! Value serialization formats.
+
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Serialize, de::DeserializeOwned};
+use crate::Error;
+use crate::compression::Framing;
+
+/// A serialization format for collection values. The format of a collection
+/// is chosen when it is created and recorded in `collection_meta` by `NAME`.
+pub trait Codec {
+  /// Name recorded in `collection_meta`.
+  const NAME: &'static str;
+  /// Whether encoded values are UTF-8 text. Text values are stored as SQLite
+  /// `TEXT`, so they can be read with the sqlite3 shell and SQL functions.
+  const TEXT: bool = false;
+
+  fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error>;
+  fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// [postcard](https://docs.rs/postcard), the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Postcard;
+
+impl Codec for Postcard {
+  const NAME: &'static str = "postcard";
+
+  fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    Ok(postcard::to_stdvec(value)?)
+  }
+
+  fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    Ok(postcard::from_bytes(bytes)?)
+  }
+}
+
+/// JSON, enabled with the `json` feature.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+  const NAME: &'static str = "json";
+  const TEXT: bool = true;
+
+  fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    serde_json::to_vec(value).map_err(|e| Error::CodecError(e.to_string()))
+  }
+
+  fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    serde_json::from_slice(bytes).map_err(|e| Error::CodecError(e.to_string()))
+  }
+}
+
+/// CBOR, enabled with the `cbor` feature.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+  const NAME: &'static str = "cbor";
+
+  fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    ciborium::into_writer(value, &mut out).map_err(|e| Error::CodecError(e.to_string()))?;
+    Ok(out)
+  }
+
+  fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    ciborium::from_reader(bytes).map_err(|e| Error::CodecError(e.to_string()))
+  }
+}
+
+/// bincode with its standard configuration, enabled with the `bincode` feature.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+  const NAME: &'static str = "bincode";
+
+  fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    bincode::serde::encode_to_vec(value, bincode::config::standard()).map_err(|e| Error::CodecError(e.to_string()))
+  }
+
+  fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let (value, _) = bincode::serde::decode_from_slice(bytes, bincode::config::standard())
+      .map_err(|e| Error::CodecError(e.to_string()))?;
+    Ok(value)
+  }
+}
+
+/// The codec of one collection, resolved for its value type, and how its
+/// output is stored.
+pub(crate) struct ValueCodec<V> {
+  pub(crate) name: &'static str,
+  pub(crate) framing: Framing,
+  text: bool,
+  encode: fn(&V) -> Result<Vec<u8>, Error>,
+  decode: fn(&[u8]) -> Result<V, Error>,
+}
+
+impl<V> Clone for ValueCodec<V> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<V> Copy for ValueCodec<V> {}
+
+impl<V: Serialize + DeserializeOwned> ValueCodec<V> {
+  pub(crate) fn of<C: Codec>() -> Self {
+    ValueCodec {
+      name: C::NAME,
+      framing: Framing::NONE,
+      text: C::TEXT,
+      encode: C::encode::<V>,
+      decode: C::decode::<V>,
+    }
+  }
+
+  /// Looks up a built-in codec by the name recorded for a collection.
+  pub(crate) fn builtin(name: &str) -> Result<Self, Error> {
+    match name {
+      Postcard::NAME => Ok(Self::of::<Postcard>()),
+      #[cfg(feature = "json")]
+      Json::NAME => Ok(Self::of::<Json>()),
+      #[cfg(feature = "cbor")]
+      Cbor::NAME => Ok(Self::of::<Cbor>()),
+      #[cfg(feature = "bincode")]
+      Bincode::NAME => Ok(Self::of::<Bincode>()),
+      _ => Err(Error::UnknownCodec(name.to_string())),
+    }
+  }
+}
+
+impl<V> ValueCodec<V> {
+  /// Encodes a value without framing; see `frame`. Index keys are derived from this form.
+  pub(crate) fn serialize(&self, value: &V) -> Result<Vec<u8>, Error> {
+    (self.encode)(value)
+  }
+
+  pub(crate) fn deserialize(&self, bytes: &[u8]) -> Result<V, Error> {
+    (self.decode)(bytes)
+  }
+
+  /// Frames the output of `serialize` for storage.
+  pub(crate) fn frame(&self, bytes: Vec<u8>) -> Result<Encoded, Error> {
+    self.framing.pack(bytes, self.text)
+  }
+
+  pub(crate) fn decode(&self, stored: &StoredBytes) -> Result<V, Error> {
+    (self.decode)(&self.framing.unpack(stored)?)
+  }
+
+  /// Converts a value stored under `old` framing to this codec's framing.
+  pub(crate) fn reframe(&self, old: &Framing, stored: &StoredBytes) -> Result<Encoded, Error> {
+    self.framing.pack(old.unpack(stored)?.into_owned(), self.text)
+  }
+}
+
+/// An encoded value, bound as `TEXT` or `BLOB` depending on the codec.
+pub(crate) struct Encoded {
+  pub(crate) bytes: Vec<u8>,
+  pub(crate) text: bool,
+}
+
+impl ToSql for Encoded {
+  fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+    Ok(ToSqlOutput::Borrowed(if self.text { ValueRef::Text(&self.bytes) } else { ValueRef::Blob(&self.bytes) }))
+  }
+}
+
+impl From<StoredBytes> for Encoded {
+  fn from(stored: StoredBytes) -> Self {
+    Encoded { bytes: stored.bytes, text: stored.text }
+  }
+}
+
+/// The bytes of a stored value, which may be `TEXT` or `BLOB`.
+#[derive(Clone, Default)]
+pub(crate) struct StoredBytes {
+  pub(crate) bytes: Vec<u8>,
+  pub(crate) text: bool,
+}
+
+impl FromSql for StoredBytes {
+  fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+    Ok(StoredBytes { bytes: value.as_bytes()?.to_vec(), text: matches!(value, ValueRef::Text(_)) })
+  }
+}
@@ -1,16 +1,25 @@
 use crate::Error;
-use crate::collection_tx::CollectionTx;
-use rusqlite::Connection;
+use crate::codec::{Codec, ValueCodec};
+use crate::compression::{Compression, Framing};
+use crate::database::register_collection;
+use crate::encryption;
+use crate::collection_tx::{CollectionTx, ReadTx};
+use crate::index::{self, Index};
+use crate::key_codec;
+use crate::pool::{Pool, Txn};
+use crate::retry::{retry, RetryPolicy};
+use crate::watch::Change;
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::sync::{Arc, mpsc};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::fmt;
 
 pub struct Collection<K, V> {
-  pub(crate) conn: Arc<Connection>,
+  pub(crate) pool: Arc<Pool>,
   pub(crate) name: String,
-  _phantom: PhantomData<(K, V)>,
+  codec: ValueCodec<V>,
+  _phantom: PhantomData<fn() -> K>,
 }
 
 impl<K, V> Collection<K, V>
@@ -18,17 +27,288 @@ where
   K: Eq + Serialize + DeserializeOwned,
   V: Serialize + DeserializeOwned,
 {
-  pub(crate) fn new(conn: Arc<Connection>, name: String) -> Self {
+  pub(crate) fn new(pool: Arc<Pool>, name: String, codec: ValueCodec<V>) -> Self {
     Collection {
-      conn,
+      pool,
       name,
+      codec,
       _phantom: PhantomData,
     }
   }
 
-  pub fn begin(&mut self) -> Result<CollectionTx<K, V>, Error> {
-    let tx = self.conn.unchecked_transaction()?;
-    Ok(CollectionTx::new(tx, self.name.clone()))
+  /// Starts a read-write transaction. Write transactions are serialized: this
+  /// waits until no other write transaction on the database is open.
+  pub fn begin(&self) -> Result<CollectionTx<'_, K, V>, Error> {
+    let tx = Txn::write(&self.pool)?;
+    let codec = self.current_codec(&tx)?;
+    Ok(CollectionTx::new(tx, self.name.clone(), codec))
+  }
+
+  /// Starts a read-only transaction. Several can be open at once, each reading
+  /// from its own snapshot of the database. In-memory databases are the
+  /// exception: see `Database::in_memory`.
+  pub fn read(&self) -> Result<ReadTx<'_, K, V>, Error> {
+    let tx = Txn::read(&self.pool)?;
+    let codec = self.current_codec(&tx)?;
+    Ok(CollectionTx::read_only(tx, self.name.clone(), codec))
+  }
+
+  /// The codec with the compression setting recorded in the snapshot of `tx`,
+  /// which another handle may have changed since this one was opened. Fails if
+  /// the collection was dropped or renamed since.
+  fn current_codec(&self, tx: &Txn<'_>) -> Result<ValueCodec<V>, Error> {
+    let mut codec = self.codec;
+    codec.framing = Framing::load(tx, &self.name)?.ok_or_else(|| Error::CollectionNotFound(self.name.clone()))?;
+    Ok(codec)
+  }
+
+  /// Creates an index named `name` over the values of the collection, keyed
+  /// by what `f` returns, and builds it from the existing entries. Every write
+  /// keeps it up to date from then on; query it with
+  /// `CollectionTx::get_by_index` and `range_by_index`.
+  ///
+  /// Only the index definition is stored, not `f`: register it again each time
+  /// the database is opened, with `CollectionBuilder::index`. An existing
+  /// index is only rebuilt if `f` returns a different type than before.
+  pub fn create_index<T, F>(&self, name: &str, f: F) -> Result<(), Error>
+  where
+    T: Serialize + DeserializeOwned,
+    F: Fn(&V) -> T + Send + Sync + 'static,
+    V: 'static,
+  {
+    self.add_index(name, false, f)
+  }
+
+  /// Like `create_index`, but no two live entries may have the same key in the
+  /// index. Writes that would break this fail with `Error::UniqueViolation`
+  /// and change nothing; so does creating the index if existing entries do.
+  pub fn create_unique_index<T, F>(&self, name: &str, f: F) -> Result<(), Error>
+  where
+    T: Serialize + DeserializeOwned,
+    F: Fn(&V) -> T + Send + Sync + 'static,
+    V: 'static,
+  {
+    self.add_index(name, true, f)
+  }
+
+  fn add_index<T, F>(&self, name: &str, unique: bool, f: F) -> Result<(), Error>
+  where
+    T: Serialize + DeserializeOwned,
+    F: Fn(&V) -> T + Send + Sync + 'static,
+    V: 'static,
+  {
+    let codec = self.codec;
+    let extract = move |raw: &[u8]| key_codec::encode_key(&f(&codec.deserialize(raw)?));
+    let index = Index::new::<T>(name, unique, Arc::new(extract));
+    let tx = Txn::write(&self.pool)?;
+    // Loads the other indexes, which the registered one is added to.
+    tx.indexes(&self.name)?;
+    if index::define::<T>(&tx, &self.name, &index)? {
+      index::build(&tx, tx.cipher(), &self.name, &index, &self.current_codec(&tx)?.framing)?;
+    }
+    self.pool.commit_with_index(tx, &self.name, index)
+  }
+
+  /// Deletes the index named `name`.
+  pub fn drop_index(&self, name: &str) -> Result<(), Error> {
+    drop_index(&self.pool, &self.name, name)
+  }
+
+  /// Fails with `Error::IndexNotRegistered` if the collection has an index
+  /// whose function was not registered, which every write would fail on. Not
+  /// checked on a read-only database.
+  fn check_indexes(&self) -> Result<(), Error> {
+    let conn = self.pool.writer()?;
+    if conn.is_readonly(rusqlite::DatabaseName::Main)? {
+      return Ok(());
+    }
+    self.pool.refresh_indexes(&conn)?;
+    index::ensure_registered(&self.pool.indexes(&conn, &self.name)?)
+  }
+
+  /// Returns a channel that receives the changes made to the collection, in
+  /// commit order, as each transaction commits. Changes that are rolled back
+  /// are never sent, and neither are entries expiring. Unsubscribe by dropping
+  /// the receiver.
+  pub fn subscribe(&self) -> mpsc::Receiver<Change<K, V>>
+  where
+    K: Send + 'static,
+    V: Send + 'static,
+  {
+    self.pool.subscribers.add(&self.name, None, self.codec)
+  }
+
+  /// Like `subscribe`, for the changes to one key, and `Change::Clear`.
+  pub fn watch<Q: Into<K>>(&self, key: Q) -> Result<mpsc::Receiver<Change<K, V>>, Error>
+  where
+    K: Send + 'static,
+    V: Send + 'static,
+  {
+    let key = key_codec::encode_key(&key.into())?;
+    Ok(self.pool.subscribers.add(&self.name, Some(key), self.codec))
+  }
+
+  /// Runs `f` in a transaction that is committed if `f` returns `Ok` and rolled
+  /// back otherwise. Busy and locked errors are retried with the default `RetryPolicy`.
+  pub fn transaction<T, F>(&self, f: F) -> Result<T, Error>
+  where
+    F: FnMut(&mut CollectionTx<'_, K, V>) -> Result<T, Error>,
+  {
+    self.transaction_with(&RetryPolicy::default(), f)
+  }
+
+  /// Like `transaction`, with an explicit retry policy.
+  pub fn transaction_with<T, F>(&self, policy: &RetryPolicy, mut f: F) -> Result<T, Error>
+  where
+    F: FnMut(&mut CollectionTx<'_, K, V>) -> Result<T, Error>,
+  {
+    retry(policy, || {
+      let mut tx = self.begin()?;
+      match f(&mut tx) {
+        Ok(value) => {
+          tx.commit()?;
+          Ok(value)
+        }
+        Err(e) => {
+          tx.rollback()?;
+          Err(e)
+        }
+      }
+    })
+  }
+}
+
+/// Deletes an index of a collection, see `Collection::drop_index`.
+pub(crate) fn drop_index(pool: &Pool, collection: &str, name: &str) -> Result<(), Error> {
+  let tx = Txn::write(pool)?;
+  if !index::exists(&tx, collection, name)? {
+    return Err(Error::IndexNotFound(name.to_string()));
+  }
+  tx.indexes(collection)?;
+  index::remove(&tx, collection, name)?;
+  tx.commit()?;
+  pool.unregister_index(collection, name);
+  Ok(())
+}
+
+/// Registers an index of a collection once it is opened.
+type RegisterIndex<K, V> = Box<dyn FnOnce(&Collection<K, V>) -> Result<(), Error> + Send>;
+
+/// Settings asked for when opening a collection. Those left as `None` keep
+/// their recorded value, or the default for a new collection.
+pub(crate) struct CollectionSettings<V> {
+  pub(crate) codec: Option<ValueCodec<V>>,
+  pub(crate) compression: Option<Compression>,
+  pub(crate) compression_threshold: Option<usize>,
+}
+
+impl<V> Default for CollectionSettings<V> {
+  fn default() -> Self {
+    CollectionSettings { codec: None, compression: None, compression_threshold: None }
+  }
+}
+
+/// Options for opening a collection, created with `Database::collection_builder`.
+pub struct CollectionBuilder<K, V> {
+  pool: Arc<Pool>,
+  name: String,
+  settings: CollectionSettings<V>,
+  indexes: Vec<RegisterIndex<K, V>>,
+}
+
+impl<K, V> CollectionBuilder<K, V>
+where
+  K: Eq + Serialize + DeserializeOwned,
+  V: Serialize + DeserializeOwned,
+{
+  pub(crate) fn new(pool: Arc<Pool>, name: String) -> Self {
+    CollectionBuilder { pool, name, settings: CollectionSettings::default(), indexes: Vec::new() }
+  }
+
+  /// Value codec of the collection. A new collection is created with it; an
+  /// existing one must have been created with it. When not set, existing
+  /// collections use their recorded codec and new ones use `Postcard`.
+  pub fn codec<C: Codec>(mut self, _codec: C) -> Self {
+    self.settings.codec = Some(ValueCodec::of::<C>());
+    self
+  }
+
+  /// Compresses values whose encoded size reaches the compression threshold.
+  /// This can be turned on or off for an existing collection; when not set,
+  /// the recorded setting is kept.
+  pub fn compression(mut self, compression: Compression) -> Self {
+    self.settings.compression = Some(compression);
+    self
+  }
+
+  /// Smallest encoded value, in bytes, that is compressed. Defaults to 256.
+  pub fn compression_threshold(mut self, bytes: usize) -> Self {
+    self.settings.compression_threshold = Some(bytes);
+    self
+  }
+
+  /// Registers the index named `name` once the collection is opened, creating
+  /// it if needed, as `Collection::create_index` does. Every index of a
+  /// collection must be registered each time the database is opened: until
+  /// then, opening the collection fails with `Error::IndexNotRegistered`,
+  /// unless the database is read-only.
+  pub fn index<T, F>(self, name: &str, f: F) -> Self
+  where
+    T: Serialize + DeserializeOwned,
+    F: Fn(&V) -> T + Send + Sync + 'static,
+    K: 'static,
+    V: 'static,
+  {
+    self.register_index(name, false, f)
+  }
+
+  /// Like `index`, for an index created with `Collection::create_unique_index`.
+  pub fn unique_index<T, F>(self, name: &str, f: F) -> Self
+  where
+    T: Serialize + DeserializeOwned,
+    F: Fn(&V) -> T + Send + Sync + 'static,
+    K: 'static,
+    V: 'static,
+  {
+    self.register_index(name, true, f)
+  }
+
+  fn register_index<T, F>(mut self, name: &str, unique: bool, f: F) -> Self
+  where
+    T: Serialize + DeserializeOwned,
+    F: Fn(&V) -> T + Send + Sync + 'static,
+    K: 'static,
+    V: 'static,
+  {
+    let name = name.to_string();
+    self.indexes.push(Box::new(move |collection| collection.add_index(&name, unique, f)));
+    self
+  }
+
+  pub fn open(self) -> Result<Collection<K, V>, Error> {
+    let codec = {
+      let conn = self.pool.writer()?;
+      let cipher = self.pool.cipher();
+      encryption::verify(&conn, cipher.as_deref())?;
+      register_collection::<K, V>(&conn, &self.name, &self.settings, cipher.as_deref())?
+    };
+    let collection = Collection::new(self.pool, self.name, codec);
+    for register in self.indexes {
+      register(&collection)?;
+    }
+    collection.check_indexes()?;
+    Ok(collection)
+  }
+}
+
+impl<K, V> Clone for Collection<K, V> {
+  fn clone(&self) -> Self {
+    Collection {
+      pool: self.pool.clone(),
+      name: self.name.clone(),
+      codec: self.codec,
+      _phantom: PhantomData,
+    }
   }
 }
 
@@ -36,6 +316,7 @@ impl<K, V> fmt::Debug for Collection<K, V> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     f.debug_struct("Collection")
       .field("name", &self.name)
+      .field("codec", &self.codec.name)
       .finish()
   }
 }
@@ -1,23 +1,101 @@
-use rusqlite::Transaction;
+use rusqlite::{CachedStatement, Connection, OptionalExtension, Statement, ToSql, params_from_iter};
+use std::collections::{HashMap, HashSet};
+use std::iter;
 use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
 use serde::{Serialize, de::DeserializeOwned};
 use crate::Error;
+use crate::key_codec;
+use crate::codec::{Encoded, StoredBytes, ValueCodec};
+use crate::encryption::{self, Cipher};
+use crate::expiry;
+use crate::index::{self, Index};
+use crate::iter::{Iter, Keys, RawIter, Values};
+use crate::page::{Page, PageCursor};
+use crate::pool::Txn;
+use crate::watch::{Change, RawChange};
 
-pub struct CollectionTx<'a, K, V> {
-  tx: Transaction<'a>,
+/// Most rows read or written by one statement of a batch operation.
+const BATCH_ROWS: usize = 256;
+
+mod sealed {
+  pub trait Sealed {}
+}
+
+/// Marker for a transaction that belongs to a single collection and commits on its own.
+pub struct ReadWrite;
+
+/// Marker for a collection view inside a `DatabaseTx`, which commits or rolls back
+/// together with the other views of that transaction.
+pub struct View;
+
+/// Marker for a read-only transaction, see `Collection::read`.
+pub struct ReadOnly;
+
+/// Modes that allow `set`, `put`, `del` and `clear`.
+pub trait Writable: sealed::Sealed {}
+
+impl sealed::Sealed for ReadWrite {}
+impl sealed::Sealed for View {}
+impl sealed::Sealed for ReadOnly {}
+impl Writable for ReadWrite {}
+impl Writable for View {}
+
+/// A typed view of one collection inside a `DatabaseTx`.
+pub type CollectionView<'a, K, V> = CollectionTx<'a, K, V, View>;
+
+/// A read-only transaction on one collection.
+pub type ReadTx<'a, K, V> = CollectionTx<'a, K, V, ReadOnly>;
+
+pub(crate) enum TxConn<'a> {
+  Owned(Txn<'a>),
+  Borrowed(&'a Txn<'a>),
+}
+
+impl<'a> TxConn<'a> {
+  fn txn(&self) -> &Txn<'a> {
+    match self {
+      TxConn::Owned(tx) => tx,
+      TxConn::Borrowed(tx) => tx,
+    }
+  }
+
+  fn cipher(&self) -> Option<&Cipher> {
+    self.txn().cipher()
+  }
+
+  fn indexes(&self, collection: &str) -> Result<Arc<[Index]>, Error> {
+    self.txn().indexes(collection)
+  }
+}
+
+impl Deref for TxConn<'_> {
+  type Target = Connection;
+
+  fn deref(&self) -> &Connection {
+    self.txn()
+  }
+}
+
+pub struct CollectionTx<'a, K, V, M = ReadWrite> {
+  tx: TxConn<'a>,
   collection: String,
-  _phantom: PhantomData<(K, V)>,
+  codec: ValueCodec<V>,
+  _phantom: PhantomData<(K, M)>,
 }
 
-impl<'a, K, V> CollectionTx<'a, K, V>
+impl<'a, K, V> CollectionTx<'a, K, V, ReadWrite>
 where
   K: Eq + Serialize + DeserializeOwned,
   V: Serialize + DeserializeOwned,
 {
-  pub(crate) fn new(tx: Transaction<'a>, name: String) -> Self {
+  pub(crate) fn new(tx: Txn<'a>, name: String, codec: ValueCodec<V>) -> Self {
     CollectionTx {
-      tx,
+      tx: TxConn::Owned(tx),
       collection: name,
+      codec,
       _phantom: PhantomData,
     }
   }
@@ -27,128 +105,846 @@ where
   }
 
   pub fn rollback(self) -> Result<(), Error> {
-    self.tx.rollback()?;
+    if let TxConn::Owned(tx) = self.tx {
+      tx.rollback()?;
+    }
     Ok(())
   }
 
   pub fn commit(self) -> Result<(), Error> {
-    self.tx.commit()?;
+    if let TxConn::Owned(tx) = self.tx {
+      tx.commit()?;
+    }
     Ok(())
   }
+}
 
+impl<'a, K, V> CollectionTx<'a, K, V, View>
+where
+  K: Eq + Serialize + DeserializeOwned,
+  V: Serialize + DeserializeOwned,
+{
+  pub(crate) fn view(tx: &'a Txn<'a>, name: String, codec: ValueCodec<V>) -> Self {
+    CollectionTx {
+      tx: TxConn::Borrowed(tx),
+      collection: name,
+      codec,
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<'a, K, V> CollectionTx<'a, K, V, ReadOnly>
+where
+  K: Eq + Serialize + DeserializeOwned,
+  V: Serialize + DeserializeOwned,
+{
+  pub(crate) fn read_only(tx: Txn<'a>, name: String, codec: ValueCodec<V>) -> Self {
+    CollectionTx {
+      tx: TxConn::Owned(tx),
+      collection: name,
+      codec,
+      _phantom: PhantomData,
+    }
+  }
+}
+
+impl<K, V, M> CollectionTx<'_, K, V, M>
+where
+  K: Eq + Serialize + DeserializeOwned,
+  V: Serialize + DeserializeOwned,
+{
   pub fn contains<Q: Into<K>>(&self, key: Q) -> Result<bool, Error> {
-    let key = key.into();
-    let key_bytes = postcard::to_stdvec(&key)?;
-    let mut stmt = self.tx.prepare("SELECT 1 FROM kv_store WHERE collection = ? AND key = ?")?;
-    let exists = stmt.exists(rusqlite::params![&self.collection, &key_bytes])?;
-    Ok(exists)
+    let key = self.entry_key(&key.into())?;
+    self.exists(&key)
   }
 
   pub fn get<Q: Into<K>>(&self, key: Q) -> Result<Option<V>, Error> {
-    let key = key.into();
-    let key_bytes = postcard::to_stdvec(&key)?;
-    let mut stmt = self.tx.prepare("SELECT value FROM kv_store WHERE collection = ? AND key = ?")?;
-    let mut rows = stmt.query(rusqlite::params![&self.collection, &key_bytes])?;
-    if let Some(row) = rows.next()? {
-      let value_bytes: Vec<u8> = row.get(0)?;
-      let value = postcard::from_bytes(&value_bytes)?;
-      Ok(Some(value))
-    } else {
-      Ok(None)
+    let key = self.entry_key(&key.into())?;
+    self.load(&key)
+  }
+
+  /// Looks up several keys at once, returning their values in the same order.
+  pub fn get_many(&self, keys: &[K]) -> Result<Vec<Option<V>>, Error> {
+    let keys = keys.iter().map(|k| self.entry_key(k)).collect::<Result<Vec<_>, _>>()?;
+    let mut slots: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (i, key) in keys.iter().enumerate() {
+      slots.entry(key.stored()).or_default().push(i);
+    }
+    let unique: Vec<&[u8]> = slots.keys().copied().collect();
+    let mut values: Vec<Option<V>> = keys.iter().map(|_| None).collect();
+    let now = expiry::now();
+    for chunk in unique.chunks(BATCH_ROWS) {
+      let sql = format!(
+        "SELECT key, value FROM kv_store WHERE collection = ? AND key IN ({})
+         AND (expires_at IS NULL OR expires_at > ?)",
+        placeholders(chunk.len(), "?"),
+      );
+      let mut stmt = prepare_batch(&self.tx, &sql, chunk.len())?;
+      let params = iter::once(&self.collection as &dyn ToSql)
+        .chain(chunk.iter().map(|k| k as &dyn ToSql))
+        .chain(iter::once(&now as &dyn ToSql));
+      let mut rows = stmt.query(params_from_iter(params))?;
+      while let Some(row) = rows.next()? {
+        let stored_key: Vec<u8> = row.get(0)?;
+        let stored: StoredBytes = row.get(1)?;
+        // A key asked for more than once is decoded for each position.
+        if let Some((&last, rest)) = slots[stored_key.as_slice()].split_last() {
+          for &i in rest {
+            values[i] = Some(self.decode_value(&keys[i].encoded, stored.clone())?);
+          }
+          values[last] = Some(self.decode_value(&keys[last].encoded, stored)?);
+        }
+      }
     }
+    Ok(values)
   }
 
-  pub fn set<Q: Into<K>, W: Into<V>>(&mut self, key: Q, val: W) -> Result<(), Error> {
-    let key = key.into();
-    let val = val.into();
-    let key_bytes = postcard::to_stdvec(&key)?;
-    let val_bytes = postcard::to_stdvec(&val)?;
-    self.tx.execute(
-      "INSERT OR REPLACE INTO kv_store (collection, key, value) VALUES (?, ?, ?)",
-      rusqlite::params![&self.collection, &key_bytes, &val_bytes],
+  /// Returns all keys in ascending key order.
+  pub fn keys(&self) -> Result<Vec<K>, Error> {
+    self.iter_keys().collect()
+  }
+
+  /// Returns all entries in ascending key order.
+  pub fn scan(&self) -> Result<Vec<(K, V)>, Error> {
+    self.select_range(Bound::Unbounded, Bound::Unbounded, false)
+  }
+
+  /// Returns all entries in descending key order.
+  pub fn scan_rev(&self) -> Result<Vec<(K, V)>, Error> {
+    self.select_range(Bound::Unbounded, Bound::Unbounded, true)
+  }
+
+  /// Returns the entries whose keys fall within `range`, in ascending key order.
+  pub fn range<R: RangeBounds<K>>(&self, range: R) -> Result<Vec<(K, V)>, Error> {
+    let (lower, upper) = self.ordered(encode_bounds(&range)?)?;
+    self.select_range(lower, upper, false)
+  }
+
+  /// Returns the entries whose keys fall within `range`, in descending key order.
+  pub fn range_rev<R: RangeBounds<K>>(&self, range: R) -> Result<Vec<(K, V)>, Error> {
+    let (lower, upper) = self.ordered(encode_bounds(&range)?)?;
+    self.select_range(lower, upper, true)
+  }
+
+  /// Returns the entries with keys greater than or equal to `start`.
+  pub fn range_from<Q: Into<K>>(&self, start: Q) -> Result<Vec<(K, V)>, Error> {
+    self.range(start.into()..)
+  }
+
+  /// Returns the entries with keys strictly less than `end`.
+  pub fn range_to<Q: Into<K>>(&self, end: Q) -> Result<Vec<(K, V)>, Error> {
+    self.range(..end.into())
+  }
+
+  /// Returns the entries whose keys start with `prefix`, in ascending key order.
+  ///
+  /// `prefix` is encoded like a key, so it must be a leading part of `K`: a
+  /// string prefix for string keys, or the first fields of a tuple or struct key.
+  /// A string or sequence at the outermost level matches any key that extends it.
+  pub fn scan_prefix<P: Serialize + ?Sized>(&self, prefix: &P) -> Result<Vec<(K, V)>, Error> {
+    self.iter_prefix(prefix)?.collect()
+  }
+
+  /// Returns the keys that start with `prefix`, in ascending order.
+  pub fn keys_prefix<P: Serialize + ?Sized>(&self, prefix: &P) -> Result<Vec<K>, Error> {
+    let (lower, upper) = self.ordered(prefix_bounds(prefix)?)?;
+    Keys::new(self.raw_iter(lower, upper, false, false)).collect()
+  }
+
+  /// Lazily iterates over the entries whose keys start with `prefix`.
+  pub fn iter_prefix<P: Serialize + ?Sized>(&self, prefix: &P) -> Result<Iter<'_, K, V>, Error> {
+    let (lower, upper) = self.ordered(prefix_bounds(prefix)?)?;
+    Ok(Iter::new(self.raw_iter(lower, upper, false, true), self.codec))
+  }
+
+  /// Returns up to `limit` entries with keys greater than `after` (or from the
+  /// start when `None`), plus a cursor to continue from. Fails with
+  /// `Error::InvalidPageLimit` if `limit` is 0.
+  pub fn page(&self, after: Option<K>, limit: usize) -> Result<Page<K, V>, Error> {
+    let lower = match after {
+      Some(key) => Bound::Excluded(self.stored_key(key_codec::encode_key(&key)?)?),
+      None => Bound::Unbounded,
+    };
+    self.page_from(lower, limit)
+  }
+
+  /// Continues a listing from the cursor returned with a previous page.
+  pub fn page_after(&self, cursor: &PageCursor, limit: usize) -> Result<Page<K, V>, Error> {
+    self.page_from(Bound::Excluded(cursor.0.clone()), limit)
+  }
+
+  fn page_from(&self, lower: Bound<Vec<u8>>, limit: usize) -> Result<Page<K, V>, Error> {
+    if limit == 0 {
+      return Err(Error::InvalidPageLimit);
+    }
+    let mut raw = self.raw_iter(lower, Bound::Unbounded, false, true).with_batch_size(limit.saturating_add(1));
+    let mut entries = Vec::new();
+    let mut last_key = None;
+    for row in raw.by_ref().take(limit) {
+      let (key_bytes, value_bytes) = row?;
+      entries.push((key_codec::decode_key(&key_bytes)?, self.codec.decode(&value_bytes)?));
+      last_key = Some(key_bytes);
+    }
+    let next = match (raw.next().transpose()?, last_key) {
+      (Some(_), Some(last_key)) => Some(PageCursor(self.stored_key(last_key)?)),
+      _ => None,
+    };
+    Ok(Page { entries, next })
+  }
+
+  /// Returns the entries whose key in `index` equals `value`, in key order.
+  pub fn get_by_index<I: Serialize + ?Sized>(&self, index: &str, value: &I) -> Result<Vec<(K, V)>, Error> {
+    let index_key = encryption::seal_index_key(self.tx.cipher(), key_codec::encode_key(value)?)?;
+    self.select_indexed(index, Bound::Included(index_key.clone()), Bound::Included(index_key))
+  }
+
+  /// Returns the entries whose key in `index` falls within `range`, ordered by
+  /// that key and then by entry key. Index keys are encrypted on an encrypted
+  /// database, where this fails with `Error::KeyOrderUnavailable` unless
+  /// `range` is unbounded.
+  pub fn range_by_index<I: Serialize, R: RangeBounds<I>>(&self, index: &str, range: R) -> Result<Vec<(K, V)>, Error> {
+    let (lower, upper) = match encode_bounds(&range)? {
+      (Bound::Unbounded, Bound::Unbounded) => (Bound::Unbounded, Bound::Unbounded),
+      _ if self.tx.cipher().is_some() => return Err(Error::KeyOrderUnavailable),
+      bounds => bounds,
+    };
+    self.select_indexed(index, lower, upper)
+  }
+
+  pub fn count(&self) -> Result<usize, Error> {
+    let mut stmt = self.tx.prepare_cached(
+      "SELECT COUNT(*) FROM kv_store WHERE collection = ? AND (expires_at IS NULL OR expires_at > ?)",
     )?;
-    Ok(())
+    let cnt: i64 = stmt.query_row(rusqlite::params![&self.collection, expiry::now()], |row| row.get(0))?;
+    Ok(cnt as usize)
   }
 
-  pub fn put<Q: Into<K>, W: Into<V>>(&mut self, key: Q, val: W) -> Result<(), Error> {
-    let key = key.into();
-    let val = val.into();
-    let key_bytes = postcard::to_stdvec(&key)?;
-    let val_bytes = postcard::to_stdvec(&val)?;
-    let result = self.tx.execute(
-      "INSERT INTO kv_store (collection, key, value) VALUES (?, ?, ?)",
-      rusqlite::params![&self.collection, &key_bytes, &val_bytes],
+  /// Lazily iterates over all entries in ascending key order.
+  pub fn iter(&self) -> Iter<'_, K, V> {
+    Iter::new(self.raw_iter(Bound::Unbounded, Bound::Unbounded, false, true), self.codec)
+  }
+
+  /// Lazily iterates over all keys in ascending order.
+  pub fn iter_keys(&self) -> Keys<'_, K> {
+    Keys::new(self.raw_iter(Bound::Unbounded, Bound::Unbounded, false, false))
+  }
+
+  /// Lazily iterates over all values in ascending key order.
+  pub fn iter_values(&self) -> Values<'_, V> {
+    Values::new(self.raw_iter(Bound::Unbounded, Bound::Unbounded, false, true), self.codec)
+  }
+
+  fn raw_iter(&self, lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>, reverse: bool, with_values: bool) -> RawIter<'_> {
+    RawIter::new(&self.tx, &self.collection, lower, upper, reverse, with_values, self.tx.cipher())
+  }
+
+  fn entry_key(&self, key: &K) -> Result<EntryKey, Error> {
+    let encoded = key_codec::encode_key(key)?;
+    let sealed = match encryption::encrypts_keys(self.tx.cipher()) {
+      true => Some(self.stored_key(encoded.clone())?),
+      false => None,
+    };
+    Ok(EntryKey { encoded, sealed })
+  }
+
+  fn exists(&self, key: &EntryKey) -> Result<bool, Error> {
+    let mut stmt = self.tx.prepare_cached(
+      "SELECT 1 FROM kv_store WHERE collection = ? AND key = ? AND (expires_at IS NULL OR expires_at > ?)",
+    )?;
+    Ok(stmt.exists(rusqlite::params![&self.collection, key.stored(), expiry::now()])?)
+  }
+
+  fn load(&self, key: &EntryKey) -> Result<Option<V>, Error> {
+    let mut stmt = self.tx.prepare_cached(
+      "SELECT value FROM kv_store WHERE collection = ? AND key = ? AND (expires_at IS NULL OR expires_at > ?)",
+    )?;
+    let mut rows = stmt.query(rusqlite::params![&self.collection, key.stored(), expiry::now()])?;
+    match rows.next()? {
+      Some(row) => Ok(Some(self.decode_value(&key.encoded, row.get(0)?)?)),
+      None => Ok(None),
+    }
+  }
+
+  /// Decrypts and decodes a value stored under the encoded key `key`.
+  fn decode_value(&self, key: &[u8], stored: StoredBytes) -> Result<V, Error> {
+    self.codec.decode(&encryption::open_value(self.tx.cipher(), key, stored)?)
+  }
+
+  /// Encrypts an encoded key if the database encrypts keys.
+  fn stored_key(&self, key_bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+    encryption::seal_key(self.tx.cipher(), key_bytes)
+  }
+
+  /// Passes key bounds through, unless keys are encrypted and so not stored in order.
+  fn ordered(&self, bounds: KeyBounds) -> Result<KeyBounds, Error> {
+    match bounds {
+      (Bound::Unbounded, Bound::Unbounded) => Ok(bounds),
+      _ if encryption::encrypts_keys(self.tx.cipher()) => Err(Error::KeyOrderUnavailable),
+      _ => Ok(bounds),
+    }
+  }
+
+  fn select_range(&self, lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>, reverse: bool) -> Result<Vec<(K, V)>, Error> {
+    Iter::new(self.raw_iter(lower, upper, reverse, true), self.codec).collect()
+  }
+
+  fn select_indexed(&self, index: &str, lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>) -> Result<Vec<(K, V)>, Error> {
+    if !index::exists(&self.tx, &self.collection, index)? {
+      return Err(Error::IndexNotFound(index.to_string()));
+    }
+    let now = expiry::now();
+    let mut sql = String::from(
+      "SELECT e.key, e.value FROM kv_index i JOIN kv_store e ON e.collection = i.collection AND e.key = i.key
+       WHERE i.collection = ? AND i.name = ? AND (e.expires_at IS NULL OR e.expires_at > ?)",
     );
-    match result {
-      Ok(_) => Ok(()),
-      Err(rusqlite::Error::SqliteFailure(e, _)) if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY => {
-        Err(Error::KeyAlreadyExists)
-      }
-      Err(e) => Err(Error::SqliteError(e)),
+    let mut params: Vec<&dyn ToSql> = vec![&self.collection, &index, &now];
+    match &lower {
+      Bound::Included(k) => { sql.push_str(" AND i.index_key >= ?"); params.push(k); }
+      Bound::Excluded(k) => { sql.push_str(" AND i.index_key > ?"); params.push(k); }
+      Bound::Unbounded => {}
+    }
+    match &upper {
+      Bound::Included(k) => { sql.push_str(" AND i.index_key <= ?"); params.push(k); }
+      Bound::Excluded(k) => { sql.push_str(" AND i.index_key < ?"); params.push(k); }
+      Bound::Unbounded => {}
     }
+    sql.push_str(" ORDER BY i.index_key, i.key");
+    let mut stmt = self.tx.prepare_cached(&sql)?;
+    let mut rows = stmt.query(params_from_iter(params))?;
+    let mut entries = Vec::new();
+    while let Some(row) = rows.next()? {
+      let key = encryption::open_key(self.tx.cipher(), row.get(0)?)?;
+      let value = self.decode_value(&key, row.get(1)?)?;
+      entries.push((key_codec::decode_key(&key)?, value));
+    }
+    Ok(entries)
   }
+}
 
-  pub fn del<Q: Into<K>>(&mut self, key: Q) -> Result<(), Error> {
-    let key = key.into();
-    let key_bytes = postcard::to_stdvec(&key)?;
-    self.tx.execute(
-      "DELETE FROM kv_store WHERE collection = ? AND key = ?",
-      rusqlite::params![&self.collection, &key_bytes],
+impl<K, V, M: Writable> CollectionTx<'_, K, V, M>
+where
+  K: Eq + Serialize + DeserializeOwned,
+  V: Serialize + DeserializeOwned,
+{
+  pub fn set<Q: Into<K>, W: Into<V>>(&mut self, key: Q, val: W) -> Result<(), Error> {
+    let key = self.entry_key(&key.into())?;
+    self.store(&key, &val.into(), Expiry::Never)
+  }
+
+  /// Like `set`, but the entry expires after `ttl`. Expired entries are skipped
+  /// by reads and deleted by `Database::purge_expired`.
+  pub fn set_with_ttl<Q: Into<K>, W: Into<V>>(&mut self, key: Q, val: W, ttl: Duration) -> Result<(), Error> {
+    let key = self.entry_key(&key.into())?;
+    self.store(&key, &val.into(), Expiry::At(expiry::deadline(ttl)))
+  }
+
+  /// Makes an existing entry expire after `ttl`. Returns whether the key was present.
+  pub fn expire<Q: Into<K>>(&mut self, key: Q, ttl: Duration) -> Result<bool, Error> {
+    let key = self.entry_key(&key.into())?;
+    let mut stmt = self.tx.prepare_cached(
+      "UPDATE kv_store SET expires_at = ? WHERE collection = ? AND key = ? AND (expires_at IS NULL OR expires_at > ?)",
     )?;
+    let updated = stmt.execute(rusqlite::params![expiry::deadline(ttl), &self.collection, key.stored(), expiry::now()])?;
+    Ok(updated > 0)
+  }
+
+  /// Inserts a new entry. Fails with `Error::KeyAlreadyExists` if the key is
+  /// present; an expired entry counts as absent.
+  pub fn put<Q: Into<K>, W: Into<V>>(&mut self, key: Q, val: W) -> Result<(), Error> {
+    let key = self.entry_key(&key.into())?;
+    self.insert(&key, &val.into())
+  }
+
+  pub fn del<Q: Into<K>>(&mut self, key: Q) -> Result<(), Error> {
+    let key = self.entry_key(&key.into())?;
+    self.delete(&key)?;
     Ok(())
   }
 
-  pub fn keys(&self) -> Result<Vec<K>, Error> {
-    let mut stmt = self.tx.prepare("SELECT key FROM kv_store WHERE collection = ?")?;
-    let rows = stmt.query_map([&self.collection], |row| {
-      let key_bytes: Vec<u8> = row.get(0)?;
-      let key = postcard::from_bytes(&key_bytes)
-        .map_err(|e| rusqlite::Error::UserFunctionError(Box::new(Error::SerializationError(e))))?;
-      Ok(key)
-    })?;
+  /// Deletes `key`, returning its value if it was present.
+  pub fn remove<Q: Into<K>>(&mut self, key: Q) -> Result<Option<V>, Error> {
+    let key = self.entry_key(&key.into())?;
+    let mut stmt = self.tx.prepare_cached(
+      "DELETE FROM kv_store WHERE collection = ? AND key = ? RETURNING value, expires_at > ?",
+    )?;
+    let mut rows = stmt.query(rusqlite::params![&self.collection, key.stored(), expiry::now()])?;
+    let value = match rows.next()? {
+      Some(row) if row.get::<_, Option<bool>>(1)? != Some(false) => Some(self.decode_value(&key.encoded, row.get(0)?)?),
+      _ => None,
+    };
+    self.unindex(&key)?;
+    if value.is_some() {
+      self.record(|| Change::Delete { key: key.encoded.clone() });
+    }
+    Ok(value)
+  }
 
-    let mut keys = Vec::new();
-    for key_result in rows {
-      let key = key_result.map_err(|e| match e {
-        rusqlite::Error::UserFunctionError(err) => *err.downcast::<Error>().unwrap(),
-        _ => Error::SqliteError(e),
-      })?;
-      keys.push(key);
+  /// Replaces the value of `key` with what `f` returns for the current one,
+  /// deleting the entry if it returns `None`. Returns the new value. The entry
+  /// keeps its expiry time, if it has one.
+  pub fn update<Q, F>(&mut self, key: Q, f: F) -> Result<Option<V>, Error>
+  where
+    Q: Into<K>,
+    F: FnOnce(Option<V>) -> Option<V>,
+  {
+    let key = self.entry_key(&key.into())?;
+    let new = f(self.load(&key)?);
+    match &new {
+      Some(val) => self.store(&key, val, Expiry::Keep)?,
+      None => {
+        self.delete(&key)?;
+      }
     }
-    Ok(keys)
+    Ok(new)
   }
 
-  pub fn scan(&self) -> Result<Vec<(K, V)>, Error> {
-    let mut stmt = self.tx.prepare("SELECT key, value FROM kv_store WHERE collection = ?")?;
-    let rows = stmt.query_map([&self.collection], |row| {
-      let key_bytes: Vec<u8> = row.get(0)?;
-      let value_bytes: Vec<u8> = row.get(1)?;
-
-      let key = postcard::from_bytes(&key_bytes)
-        .map_err(|e| rusqlite::Error::UserFunctionError(Box::new(Error::SerializationError(e))))?;
-      let value = postcard::from_bytes(&value_bytes)
-        .map_err(|e| rusqlite::Error::UserFunctionError(Box::new(Error::SerializationError(e))))?;
-      Ok((key, value))
-    })?;
+  /// Returns the value of `key`, first inserting the one `f` returns if the key is absent.
+  pub fn get_or_insert_with<Q, F>(&mut self, key: Q, f: F) -> Result<V, Error>
+  where
+    Q: Into<K>,
+    F: FnOnce() -> V,
+  {
+    let key = self.entry_key(&key.into())?;
+    if let Some(val) = self.load(&key)? {
+      return Ok(val);
+    }
+    let val = f();
+    self.store(&key, &val, Expiry::Keep)?;
+    Ok(val)
+  }
 
-    let mut entries = Vec::new();
-    for entry_result in rows {
-      entries.push(entry_result.map_err(|e| match e {
-        rusqlite::Error::UserFunctionError(err) => *err.downcast::<Error>().unwrap(),
-        _ => Error::SqliteError(e),
-      })?);
+  /// Inserts the value `insert` returns if `key` is absent, or applies `modify`
+  /// to the current value otherwise, keeping its expiry time. Returns the
+  /// stored value.
+  pub fn upsert_with<Q, I, F>(&mut self, key: Q, insert: I, modify: F) -> Result<V, Error>
+  where
+    Q: Into<K>,
+    I: FnOnce() -> V,
+    F: FnOnce(&mut V),
+  {
+    let key = self.entry_key(&key.into())?;
+    let val = match self.load(&key)? {
+      Some(mut val) => {
+        modify(&mut val);
+        val
+      }
+      None => insert(),
+    };
+    self.store(&key, &val, Expiry::Keep)?;
+    Ok(val)
+  }
+
+  /// Sets several entries, as `set` would one by one.
+  pub fn set_many<I, Q, W>(&mut self, entries: I) -> Result<(), Error>
+  where
+    I: IntoIterator<Item = (Q, W)>,
+    Q: Into<K>,
+    W: Into<V>,
+  {
+    let mut entries = entries.into_iter();
+    let indexes = self.tx.indexes(&self.collection)?;
+    if self.one_by_one(&indexes) {
+      return entries.try_for_each(|(key, val)| self.set(key, val));
+    }
+    loop {
+      let chunk = self.encode_entries(&indexes, entries.by_ref().take(BATCH_ROWS))?;
+      if chunk.is_empty() {
+        return Ok(());
+      }
+      let sql = format!(
+        "INSERT OR REPLACE INTO kv_store (collection, key, value) VALUES {}",
+        placeholders(chunk.len(), "(?1, ?, ?)"),
+      );
+      let mut stmt = prepare_batch(&self.tx, &sql, chunk.len())?;
+      stmt.execute(params_from_iter(entry_params(&self.collection, &chunk)))?;
+      // Of a key given twice, the last value is the one stored.
+      for (_, key, val) in &chunk {
+        self.reindex(&indexes, key, val)?;
+      }
     }
-    Ok(entries)
+  }
+
+  /// Inserts the entries whose keys do not exist yet, and returns the keys that
+  /// did, in the order given. Unlike `put`, existing keys are not an error. As
+  /// with `put`, expired entries count as absent.
+  pub fn put_many<I, Q, W>(&mut self, entries: I) -> Result<Vec<K>, Error>
+  where
+    I: IntoIterator<Item = (Q, W)>,
+    Q: Into<K>,
+    W: Into<V>,
+  {
+    let mut entries = entries.into_iter();
+    let mut existing = Vec::new();
+    let now = expiry::now();
+    let indexes = self.tx.indexes(&self.collection)?;
+    if self.one_by_one(&indexes) {
+      for (key, val) in entries {
+        let key = key.into();
+        match self.insert(&self.entry_key(&key)?, &val.into()) {
+          Err(Error::KeyAlreadyExists) => existing.push(key),
+          result => result?,
+        }
+      }
+      return Ok(existing);
+    }
+    loop {
+      let chunk = self.encode_entries(&indexes, entries.by_ref().take(BATCH_ROWS))?;
+      if chunk.is_empty() {
+        return Ok(existing);
+      }
+      let sql = format!(
+        "INSERT INTO kv_store (collection, key, value) VALUES {}
+         ON CONFLICT DO UPDATE SET value = excluded.value, expires_at = NULL WHERE kv_store.expires_at <= ?
+         RETURNING key",
+        placeholders(chunk.len(), "(?1, ?, ?)"),
+      );
+      let mut stmt = prepare_batch(&self.tx, &sql, chunk.len())?;
+      let mut params = entry_params(&self.collection, &chunk);
+      params.push(&now);
+      let mut inserted = stmt.query_map(params_from_iter(params), |row| row.get(0))?
+        .collect::<Result<HashSet<Vec<u8>>, _>>()?;
+      // Of a key given twice, only the first occurrence can have been inserted.
+      for (key, entry_key, val) in chunk {
+        match inserted.remove(entry_key.stored()) {
+          true => self.reindex(&indexes, &entry_key, &val)?,
+          false => existing.push(key),
+        }
+      }
+    }
+  }
+
+  /// Deletes several keys, returning how many entries were deleted.
+  pub fn del_many(&mut self, keys: &[K]) -> Result<usize, Error> {
+    let keys = keys.iter().map(|k| self.entry_key(k)).collect::<Result<Vec<_>, _>>()?;
+    if self.tx.txn().watched(&self.collection) {
+      return keys.iter().try_fold(0, |deleted, key| Ok(deleted + self.delete(key)? as usize));
+    }
+    let mut deleted = 0;
+    let now = expiry::now();
+    let indexed = !self.tx.indexes(&self.collection)?.is_empty();
+    for chunk in keys.chunks(BATCH_ROWS) {
+      let sql = format!(
+        "DELETE FROM kv_store WHERE collection = ? AND key IN ({}) RETURNING expires_at IS NULL OR expires_at > ?",
+        placeholders(chunk.len(), "?"),
+      );
+      let mut stmt = prepare_batch(&self.tx, &sql, chunk.len())?;
+      let params = iter::once(&self.collection as &dyn ToSql)
+        .chain(chunk.iter().map(|k| k as &dyn ToSql))
+        .chain(iter::once(&now as &dyn ToSql));
+      // Expired entries are deleted too, but not counted.
+      for live in stmt.query_map(params_from_iter(params), |row| row.get::<_, bool>(0))? {
+        deleted += live? as usize;
+      }
+      if indexed {
+        let sql = format!("DELETE FROM kv_index WHERE collection = ? AND key IN ({})", placeholders(chunk.len(), "?"));
+        let params = iter::once(&self.collection as &dyn ToSql).chain(chunk.iter().map(|k| k as &dyn ToSql));
+        prepare_batch(&self.tx, &sql, chunk.len())?.execute(params_from_iter(params))?;
+      }
+    }
+    Ok(deleted)
   }
 
   pub fn clear(&mut self) -> Result<(), Error> {
-    self.tx.execute("DELETE FROM kv_store WHERE collection = ?", [&self.collection])?;
+    self.tx.prepare_cached("DELETE FROM kv_store WHERE collection = ?")?.execute([&self.collection])?;
+    if !self.tx.indexes(&self.collection)?.is_empty() {
+      self.tx.prepare_cached("DELETE FROM kv_index WHERE collection = ?")?.execute([&self.collection])?;
+    }
+    self.record(|| Change::Clear);
     Ok(())
   }
 
-  pub fn count(&self) -> Result<usize, Error> {
-    let mut stmt = self.tx.prepare("SELECT COUNT(*) FROM kv_store WHERE collection = ?")?;
-    let cnt: i64 = stmt.query_row([&self.collection], |row| row.get(0))?;
-    Ok(cnt as usize)
+  /// Sets `key` to `new`, or deletes it if `new` is `None`, provided its current
+  /// value equals `expected` (`None` meaning the key is absent). Otherwise fails
+  /// with `Error::ConditionFailed`, holding the current value. A replaced entry
+  /// keeps its expiry time.
+  pub fn compare_and_swap<Q: Into<K>>(&mut self, key: Q, expected: Option<V>, new: Option<V>) -> Result<(), Error>
+  where
+    V: PartialEq + Send + Sync + 'static,
+  {
+    self.write_if(key.into(), |current| current == expected.as_ref(), new)
+  }
+
+  /// Sets `key` to `new` if `predicate` accepts its current value, keeping its
+  /// expiry time. Otherwise fails with `Error::ConditionFailed`, holding the
+  /// current value.
+  pub fn update_if<Q, W, F>(&mut self, key: Q, predicate: F, new: W) -> Result<(), Error>
+  where
+    Q: Into<K>,
+    W: Into<V>,
+    F: FnOnce(Option<&V>) -> bool,
+    V: Send + Sync + 'static,
+  {
+    self.write_if(key.into(), predicate, Some(new.into()))
+  }
+
+  fn write_if<F>(&mut self, key: K, predicate: F, new: Option<V>) -> Result<(), Error>
+  where
+    F: FnOnce(Option<&V>) -> bool,
+    V: Send + Sync + 'static,
+  {
+    let key = self.entry_key(&key)?;
+    let current = self.load(&key)?;
+    if !predicate(current.as_ref()) {
+      return Err(Error::ConditionFailed { current: current.map(|v| Box::new(v) as _) });
+    }
+    match new {
+      Some(val) => self.store(&key, &val, Expiry::Keep),
+      None => self.delete(&key).map(|_| ()),
+    }
+  }
+
+  fn encode_entries<Q: Into<K>, W: Into<V>>(
+    &self,
+    indexes: &[Index],
+    entries: impl Iterator<Item = (Q, W)>,
+  ) -> Result<Vec<(K, EntryKey, EntryValue)>, Error> {
+    entries.map(|(key, val)| {
+      let key = key.into();
+      let entry_key = self.entry_key(&key)?;
+      let val = self.encode_value(indexes, &entry_key, &val.into())?;
+      Ok((key, entry_key, val))
+    }).collect()
+  }
+
+  fn encode_value(&self, indexes: &[Index], key: &EntryKey, val: &V) -> Result<EntryValue, Error> {
+    let raw = self.codec.serialize(val)?;
+    let index_keys = indexes.iter()
+      .map(|index| encryption::seal_index_key(self.tx.cipher(), index.key(&raw)?))
+      .collect::<Result<_, _>>()?;
+    let encoded = encryption::seal_value(self.tx.cipher(), &key.encoded, self.codec.frame(raw)?)?;
+    Ok(EntryValue { encoded, index_keys })
+  }
+
+  fn store(&self, key: &EntryKey, val: &V, expiry: Expiry) -> Result<(), Error> {
+    let indexes = self.tx.indexes(&self.collection)?;
+    let encoded = self.encode_value(&indexes, key, val)?;
+    self.check_unique(&indexes, key, &encoded)?;
+    // Subscribers are told whether this is an insert or an update.
+    let existed = self.tx.txn().watched(&self.collection) && self.exists(key)?;
+    let (expires_at, keep) = match expiry {
+      Expiry::Never => (None, false),
+      Expiry::At(at) => (Some(at), false),
+      Expiry::Keep => (None, true),
+    };
+    // The deadline of an expired entry is never carried over to its replacement.
+    let mut stmt = self.tx.prepare_cached(
+      "INSERT INTO kv_store (collection, key, value, expires_at) VALUES (?1, ?2, ?3, ?4)
+       ON CONFLICT DO UPDATE SET value = excluded.value,
+       expires_at = CASE WHEN ?5 AND kv_store.expires_at > ?6 THEN kv_store.expires_at ELSE excluded.expires_at END",
+    )?;
+    stmt.execute(rusqlite::params![&self.collection, key.stored(), &encoded, expires_at, keep, expiry::now()])?;
+    self.reindex(&indexes, key, &encoded)?;
+    self.record_value(key, val, existed)
+  }
+
+  /// Stores a new entry, or replaces an expired one.
+  fn insert(&self, key: &EntryKey, val: &V) -> Result<(), Error> {
+    let indexes = self.tx.indexes(&self.collection)?;
+    let encoded = self.encode_value(&indexes, key, val)?;
+    self.check_unique(&indexes, key, &encoded)?;
+    let mut stmt = self.tx.prepare_cached(
+      "INSERT INTO kv_store (collection, key, value) VALUES (?, ?, ?)
+       ON CONFLICT DO UPDATE SET value = excluded.value, expires_at = NULL WHERE kv_store.expires_at <= ?",
+    )?;
+    if stmt.execute(rusqlite::params![&self.collection, key.stored(), &encoded, expiry::now()])? == 0 {
+      return Err(Error::KeyAlreadyExists);
+    }
+    self.reindex(&indexes, key, &encoded)?;
+    self.record_value(key, val, false)
   }
+
+  /// Deletes an entry, returning whether it was present and not expired.
+  fn delete(&self, key: &EntryKey) -> Result<bool, Error> {
+    let mut stmt = self.tx.prepare_cached(
+      "DELETE FROM kv_store WHERE collection = ? AND key = ? RETURNING expires_at IS NULL OR expires_at > ?",
+    )?;
+    let live = stmt.query_row(rusqlite::params![&self.collection, key.stored(), expiry::now()], |row| row.get(0))
+      .optional()?
+      .unwrap_or(false);
+    self.unindex(key)?;
+    if live {
+      self.record(|| Change::Delete { key: key.encoded.clone() });
+    }
+    Ok(live)
+  }
+
+  /// Whether batch writes must go entry by entry, to check unique indexes or
+  /// to tell inserts from updates for subscribers.
+  fn one_by_one(&self, indexes: &[Index]) -> bool {
+    indexes.iter().any(|index| index.unique) || self.tx.txn().watched(&self.collection)
+  }
+
+  /// Records a change for the subscribers of the collection, if it has any.
+  fn record(&self, change: impl FnOnce() -> RawChange) {
+    if self.tx.txn().watched(&self.collection) {
+      self.tx.txn().record(&self.collection, change());
+    }
+  }
+
+  fn record_value(&self, key: &EntryKey, val: &V, existed: bool) -> Result<(), Error> {
+    if self.tx.txn().watched(&self.collection) {
+      let (key, value) = (key.encoded.clone(), self.codec.serialize(val)?);
+      let change = match existed {
+        true => Change::Update { key, value },
+        false => Change::Insert { key, value },
+      };
+      self.tx.txn().record(&self.collection, change);
+    }
+    Ok(())
+  }
+
+  /// Fails with `Error::UniqueViolation` if another live entry has the same key
+  /// as `val` in one of the unique indexes.
+  fn check_unique(&self, indexes: &[Index], key: &EntryKey, val: &EntryValue) -> Result<(), Error> {
+    for (index, index_key) in indexes.iter().zip(&val.index_keys).filter(|(index, _)| index.unique) {
+      let mut stmt = self.tx.prepare_cached(
+        "SELECT i.key FROM kv_index i JOIN kv_store e ON e.collection = i.collection AND e.key = i.key
+         WHERE i.collection = ? AND i.name = ? AND i.index_key = ? AND i.key != ?
+         AND (e.expires_at IS NULL OR e.expires_at > ?) LIMIT 1",
+      )?;
+      let holder: Option<Vec<u8>> = stmt.query_row(
+        rusqlite::params![&self.collection, &index.name, index_key, key.stored(), expiry::now()],
+        |row| row.get(0),
+      ).optional()?;
+      if let Some(holder) = holder {
+        let key = encryption::open_key(self.tx.cipher(), holder)?;
+        return Err(Error::UniqueViolation { index: index.name.clone(), key });
+      }
+    }
+    Ok(())
+  }
+
+  /// Replaces the index entries of a key with those of its new value.
+  fn reindex(&self, indexes: &[Index], key: &EntryKey, val: &EntryValue) -> Result<(), Error> {
+    if indexes.is_empty() {
+      return Ok(());
+    }
+    self.unindex(key)?;
+    let mut stmt = self.tx.prepare_cached("INSERT INTO kv_index (collection, name, index_key, key) VALUES (?, ?, ?, ?)")?;
+    for (index, index_key) in indexes.iter().zip(&val.index_keys) {
+      stmt.execute(rusqlite::params![&self.collection, &index.name, index_key, key.stored()])?;
+    }
+    Ok(())
+  }
+
+  fn unindex(&self, key: &EntryKey) -> Result<(), Error> {
+    if !self.tx.indexes(&self.collection)?.is_empty() {
+      let mut stmt = self.tx.prepare_cached("DELETE FROM kv_index WHERE collection = ? AND key = ?")?;
+      stmt.execute(rusqlite::params![&self.collection, key.stored()])?;
+    }
+    Ok(())
+  }
+}
+
+/// What a write does to the expiry time of the entry it replaces.
+enum Expiry {
+  /// The entry never expires.
+  Never,
+  /// The entry expires at this time, see `expiry::deadline`.
+  At(i64),
+  /// The entry keeps the expiry time of the live entry it replaces, if any.
+  Keep,
+}
+
+/// A key encoded for one operation. Values are bound to the `encoded` form,
+/// while `kv_store` holds the `sealed` form when keys are encrypted.
+struct EntryKey {
+  encoded: Vec<u8>,
+  sealed: Option<Vec<u8>>,
+}
+
+impl EntryKey {
+  fn stored(&self) -> &[u8] {
+    self.sealed.as_deref().unwrap_or(&self.encoded)
+  }
+}
+
+impl ToSql for EntryKey {
+  fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+    self.stored().to_sql()
+  }
+}
+
+/// A value encoded for storage, with its stored key in each index of the
+/// collection.
+struct EntryValue {
+  encoded: Encoded,
+  index_keys: Vec<Vec<u8>>,
+}
+
+impl ToSql for EntryValue {
+  fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+    self.encoded.to_sql()
+  }
+}
+
+/// A statement of a batch operation, see `prepare_batch`.
+enum BatchStatement<'c> {
+  Cached(CachedStatement<'c>),
+  Uncached(Statement<'c>),
+}
+
+impl<'c> Deref for BatchStatement<'c> {
+  type Target = Statement<'c>;
+
+  fn deref(&self) -> &Statement<'c> {
+    match self {
+      BatchStatement::Cached(stmt) => stmt,
+      BatchStatement::Uncached(stmt) => stmt,
+    }
+  }
+}
+
+impl DerefMut for BatchStatement<'_> {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    match self {
+      BatchStatement::Cached(stmt) => stmt,
+      BatchStatement::Uncached(stmt) => stmt,
+    }
+  }
+}
+
+/// Prepares a statement for a batch of `rows` rows. Only full batches go through
+/// the statement cache: the SQL differs with each batch size, and caching every
+/// size would push out the statements of point operations.
+fn prepare_batch<'c>(conn: &'c Connection, sql: &str, rows: usize) -> Result<BatchStatement<'c>, Error> {
+  Ok(match rows {
+    BATCH_ROWS => BatchStatement::Cached(conn.prepare_cached(sql)?),
+    _ => BatchStatement::Uncached(conn.prepare(sql)?),
+  })
+}
+
+/// `count` copies of `row`, separated by commas.
+fn placeholders(count: usize, row: &str) -> String {
+  vec![row; count].join(", ")
+}
+
+/// Parameters for `(?1, ?, ?)` rows: the collection, then each key and value.
+fn entry_params<'a, K>(collection: &'a dyn ToSql, entries: &'a [(K, EntryKey, EntryValue)]) -> Vec<&'a dyn ToSql> {
+  iter::once(collection)
+    .chain(entries.iter().flat_map(|(_, key, val)| [key as &dyn ToSql, val as &dyn ToSql]))
+    .collect()
+}
+
+type KeyBounds = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+fn encode_bounds<K: Serialize, R: RangeBounds<K>>(range: &R) -> Result<KeyBounds, Error> {
+  let encode = |bound: Bound<&K>| -> Result<Bound<Vec<u8>>, Error> {
+    Ok(match bound {
+      Bound::Included(k) => Bound::Included(key_codec::encode_key(k)?),
+      Bound::Excluded(k) => Bound::Excluded(key_codec::encode_key(k)?),
+      Bound::Unbounded => Bound::Unbounded,
+    })
+  };
+  Ok((encode(range.start_bound())?, encode(range.end_bound())?))
+}
+
+fn prefix_bounds<P: Serialize + ?Sized>(prefix: &P) -> Result<KeyBounds, Error> {
+  let start = key_codec::encode_prefix(prefix)?;
+  let end = match key_codec::prefix_successor(&start) {
+    Some(end) => Bound::Excluded(end),
+    None => Bound::Unbounded,
+  };
+  Ok((Bound::Included(start), end))
 }
@@ -0,0 +1,141 @@
+//! Optional compression of stored values.
+//!
+//! In a collection with compression enabled, every value stored as a BLOB
+//! starts with a byte telling how the rest is compressed, so values below the
+//! threshold, or written under an earlier setting, are read back as they are.
+//! Values of text codecs that are left uncompressed stay plain `TEXT`.
+
+use rusqlite::{Connection, OptionalExtension};
+use std::borrow::Cow;
+use crate::Error;
+use crate::codec::{Encoded, StoredBytes};
+
+/// Encoded values smaller than this many bytes are not compressed by default.
+pub(crate) const DEFAULT_THRESHOLD: usize = 256;
+
+const TAG_RAW: u8 = 0;
+const TAG_ZSTD: u8 = 1;
+const TAG_LZ4: u8 = 2;
+
+/// Compression algorithm of a collection, see `CollectionBuilder::compression`.
+/// Which variants exist depends on the enabled features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+  None,
+  /// zstd at its default level, enabled with the `zstd` feature.
+  #[cfg(feature = "zstd")]
+  Zstd,
+  /// LZ4, enabled with the `lz4` feature.
+  #[cfg(feature = "lz4")]
+  Lz4,
+}
+
+impl Compression {
+  /// Name recorded in `collection_meta`.
+  pub(crate) fn name(self) -> &'static str {
+    match self {
+      Compression::None => "none",
+      #[cfg(feature = "zstd")]
+      Compression::Zstd => "zstd",
+      #[cfg(feature = "lz4")]
+      Compression::Lz4 => "lz4",
+    }
+  }
+
+  pub(crate) fn from_name(name: &str) -> Result<Self, Error> {
+    match name {
+      "none" => Ok(Compression::None),
+      #[cfg(feature = "zstd")]
+      "zstd" => Ok(Compression::Zstd),
+      #[cfg(feature = "lz4")]
+      "lz4" => Ok(Compression::Lz4),
+      _ => Err(Error::UnknownCompression(name.to_string())),
+    }
+  }
+
+  /// Returns the header tag and compressed bytes, or `None` when there is nothing to compress with.
+  #[cfg_attr(not(any(feature = "zstd", feature = "lz4")), allow(unused_variables))]
+  fn compress(self, bytes: &[u8]) -> Result<Option<(u8, Vec<u8>)>, Error> {
+    match self {
+      Compression::None => Ok(None),
+      #[cfg(feature = "zstd")]
+      Compression::Zstd => Ok(Some((TAG_ZSTD, zstd::bulk::compress(bytes, 0)?))),
+      #[cfg(feature = "lz4")]
+      Compression::Lz4 => Ok(Some((TAG_LZ4, lz4_flex::compress_prepend_size(bytes)))),
+    }
+  }
+}
+
+/// How the values of one collection are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Framing {
+  pub(crate) compression: Compression,
+  pub(crate) threshold: usize,
+}
+
+impl Framing {
+  pub(crate) const NONE: Framing = Framing { compression: Compression::None, threshold: DEFAULT_THRESHOLD };
+
+  /// Reads the recorded framing of a collection, or `None` if it does not exist.
+  pub(crate) fn load(conn: &Connection, collection: &str) -> Result<Option<Framing>, Error> {
+    let stored: Option<(String, usize)> = conn.prepare_cached(
+      "SELECT compression, compression_threshold FROM collection_meta WHERE name = ?",
+    )?.query_row([collection], |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+    stored.map(|(compression, threshold)| Ok(Framing { compression: Compression::from_name(&compression)?, threshold }))
+      .transpose()
+  }
+
+  /// Whether values carry a header byte.
+  pub(crate) fn framed(&self) -> bool {
+    self.compression != Compression::None
+  }
+
+  /// Wraps the output of a codec for storage.
+  pub(crate) fn pack(&self, bytes: Vec<u8>, text: bool) -> Result<Encoded, Error> {
+    if !self.framed() {
+      return Ok(Encoded { bytes, text });
+    }
+    if bytes.len() >= self.threshold {
+      if let Some((tag, compressed)) = self.compression.compress(&bytes)? {
+        if compressed.len() + 1 < bytes.len() {
+          let mut out = Vec::with_capacity(compressed.len() + 1);
+          out.push(tag);
+          out.extend_from_slice(&compressed);
+          return Ok(Encoded { bytes: out, text: false });
+        }
+      }
+    }
+    if text {
+      return Ok(Encoded { bytes, text });
+    }
+    let mut out = Vec::with_capacity(bytes.len() + 1);
+    out.push(TAG_RAW);
+    out.extend_from_slice(&bytes);
+    Ok(Encoded { bytes: out, text: false })
+  }
+
+  /// Recovers the output of the codec from a stored value.
+  pub(crate) fn unpack<'a>(&self, stored: &'a StoredBytes) -> Result<Cow<'a, [u8]>, Error> {
+    if !self.framed() || stored.text {
+      return Ok(Cow::Borrowed(&stored.bytes));
+    }
+    let Some((&tag, rest)) = stored.bytes.split_first() else {
+      return Err(Error::CompressionError("empty value".into()));
+    };
+    match tag {
+      TAG_RAW => Ok(Cow::Borrowed(rest)),
+      #[cfg(feature = "zstd")]
+      TAG_ZSTD => Ok(Cow::Owned(zstd::stream::decode_all(rest)?)),
+      #[cfg(not(feature = "zstd"))]
+      TAG_ZSTD => Err(Error::UnknownCompression("zstd".into())),
+      #[cfg(feature = "lz4")]
+      TAG_LZ4 => lz4_flex::decompress_size_prepended(rest)
+        .map(Cow::Owned)
+        .map_err(|e| Error::CompressionError(e.to_string())),
+      #[cfg(not(feature = "lz4"))]
+      TAG_LZ4 => Err(Error::UnknownCompression("lz4".into())),
+      _ => Err(Error::CompressionError(format!("unknown value header {tag:#04x}"))),
+    }
+  }
+}
@@ -1,18 +1,116 @@
-use rusqlite::Connection;
-use std::path::Path;
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior};
+use std::path::{Path, PathBuf};
 use crate::Error;
-use crate::collection::Collection;
+use crate::codec::{Codec, Postcard, StoredBytes, ValueCodec};
+use crate::collection::{Collection, CollectionBuilder, CollectionSettings};
+use crate::compression::{self, Compression, Framing};
+use crate::encryption::{self, Cipher};
+use crate::expiry::{self, ExpirySweeper};
+use crate::index;
+use crate::collection_tx::CollectionView;
+use crate::database_tx::DatabaseTx;
+use crate::options::DatabaseOptions;
+use crate::pool::{Pool, Txn};
+use crate::retry::{retry, RetryPolicy};
+use crate::key_codec;
+use crate::schema;
 use std::any::type_name;
 use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
 
+/// Key encoding written by storedb 2.0 and earlier (postcard).
+const KEY_ENCODING_POSTCARD: i64 = 0;
+/// Order-preserving key encoding, see `key_codec`.
+const KEY_ENCODING_ORDERED: i64 = 1;
+/// Rows read per query when rewriting the entries of a collection on open.
+const UPGRADE_BATCH: usize = 512;
+
+/// Schema upgrades applied on open. `PRAGMA user_version` records how many have run.
+const SCHEMA_UPGRADES: &[&str] = &[
+  "ALTER TABLE collection_meta ADD COLUMN key_encoding INTEGER NOT NULL DEFAULT 0;",
+  "ALTER TABLE collection_meta ADD COLUMN key_schema TEXT;
+   ALTER TABLE collection_meta ADD COLUMN value_schema TEXT;",
+  "ALTER TABLE collection_meta ADD COLUMN version INTEGER NOT NULL DEFAULT 0;",
+  "ALTER TABLE collection_meta ADD COLUMN codec TEXT NOT NULL DEFAULT 'postcard';",
+  "ALTER TABLE collection_meta ADD COLUMN compression TEXT NOT NULL DEFAULT 'none';
+   ALTER TABLE collection_meta ADD COLUMN compression_threshold INTEGER NOT NULL DEFAULT 256;",
+  "CREATE TABLE database_meta (name TEXT PRIMARY KEY, value BLOB NOT NULL);",
+  "ALTER TABLE kv_store ADD COLUMN expires_at INTEGER;
+   CREATE INDEX kv_store_expiry ON kv_store (expires_at) WHERE expires_at IS NOT NULL;",
+  "CREATE TABLE index_meta (
+     collection TEXT NOT NULL,
+     name TEXT NOT NULL,
+     key_type TEXT NOT NULL,
+     key_schema TEXT,
+     PRIMARY KEY (collection, name)
+   );
+   CREATE TABLE kv_index (
+     collection TEXT NOT NULL,
+     name TEXT NOT NULL,
+     index_key BLOB NOT NULL,
+     key BLOB NOT NULL,
+     PRIMARY KEY (collection, name, index_key, key)
+   ) WITHOUT ROWID;
+   CREATE INDEX kv_index_entry ON kv_index (collection, key);",
+  "ALTER TABLE index_meta ADD COLUMN is_unique INTEGER NOT NULL DEFAULT 0;",
+];
+
+/// A collection as recorded in `collection_meta`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionInfo {
+  pub name: String,
+  pub key_type: String,
+  pub value_type: String,
+  /// Schema version, incremented by each `Database::migrate_collection`.
+  pub version: u32,
+  /// Name of the value codec, see `Codec::NAME`.
+  pub codec: String,
+  /// Name of the compression algorithm, or `"none"`.
+  pub compression: String,
+}
+
+/// Handle to a storedb database. Cheap to clone and safe to share between
+/// threads; all clones use the same connection pool.
+#[derive(Clone)]
 pub struct Database {
-  conn: Arc<Connection>,
+  pool: Arc<Pool>,
 }
 
 impl Database {
   pub fn new<P: AsRef<Path>>(db_path: P) -> Result<Self, Error> {
-    let conn = Connection::open(db_path)?;
-    conn.execute_batch(r#"
+    DatabaseOptions::new(db_path).open()
+  }
+
+  /// Returns options for opening the database at `db_path` with non-default settings.
+  pub fn builder<P: AsRef<Path>>(db_path: P) -> DatabaseOptions {
+    DatabaseOptions::new(db_path)
+  }
+
+  /// Opens a private in-memory database. Read transactions share the writer
+  /// connection, so they are serialized with writes: a read waits for any open
+  /// transaction to finish, and fails with `Error::NestedTransaction` if that
+  /// transaction is on the same thread.
+  pub fn in_memory() -> Result<Self, Error> {
+    DatabaseOptions::new(":memory:").open()
+  }
+
+  /// Opens a database in a new temporary directory, which is deleted when the
+  /// last handle to the database is dropped.
+  pub fn temporary() -> Result<Self, Error> {
+    let dir = TempDir::new()?;
+    Database::open(DatabaseOptions::new(dir.path().join("storedb.db")), Some(dir))
+  }
+
+  /// Path of the database file, or `None` for in-memory databases.
+  pub fn path(&self) -> Option<&Path> {
+    self.pool.path()
+  }
+
+  pub(crate) fn open(options: DatabaseOptions, temp_dir: Option<TempDir>) -> Result<Self, Error> {
+    let conn = options.open_writer()?;
+    if !options.read_only {
+      conn.execute_batch(r#"
             CREATE TABLE IF NOT EXISTS collection_meta (
                 name TEXT PRIMARY KEY,
                 key_type TEXT NOT NULL,
@@ -25,38 +123,546 @@ impl Database {
                 PRIMARY KEY(collection, key)
             );
         "#)?;
-    Ok(Database { conn: Arc::new(conn) })
+      upgrade_schema(&conn)?;
+    } else if schema_version(&conn)? < SCHEMA_UPGRADES.len() {
+      return Err(Error::UpgradeRequired);
+    }
+    let cipher = encryption::open_cipher(&conn, &options)?;
+    let path = conn.path().filter(|p| !p.is_empty()).map(PathBuf::from);
+    Ok(Database { pool: Arc::new(Pool::new(conn, path, options, cipher, temp_dir)) })
+  }
+
+  pub fn get_collection<K, V>(&self, name: &str) -> Result<Collection<K, V>, Error>
+  where
+    K: Eq + serde::Serialize + serde::de::DeserializeOwned,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+  {
+    self.collection_builder(name).open()
+  }
+
+  /// Returns options for opening the named collection with non-default settings,
+  /// such as its value codec.
+  pub fn collection_builder<K, V>(&self, name: &str) -> CollectionBuilder<K, V>
+  where
+    K: Eq + serde::Serialize + serde::de::DeserializeOwned,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+  {
+    CollectionBuilder::new(self.pool.clone(), name.to_string())
+  }
+
+  /// Lists all collections, ordered by name.
+  pub fn list_collections(&self) -> Result<Vec<CollectionInfo>, Error> {
+    let conn = self.pool.reader()?;
+    let mut stmt = conn.prepare("SELECT name, key_type, value_type, version, codec, compression FROM collection_meta ORDER BY name")?;
+    let mut rows = stmt.query([])?;
+    let mut collections = Vec::new();
+    while let Some(row) = rows.next()? {
+      collections.push(CollectionInfo {
+        name: row.get(0)?,
+        key_type: row.get(1)?,
+        value_type: row.get(2)?,
+        version: row.get(3)?,
+        codec: row.get(4)?,
+        compression: row.get(5)?,
+      });
+    }
+    Ok(collections)
+  }
+
+  /// Returns the schema version of a collection, 0 until it is first migrated.
+  pub fn collection_version(&self, name: &str) -> Result<u32, Error> {
+    let conn = self.pool.reader()?;
+    conn.query_row("SELECT version FROM collection_meta WHERE name = ?", [name], |row| row.get(0))
+      .optional()?
+      .ok_or_else(|| Error::CollectionNotFound(name.to_string()))
+  }
+
+  /// Rewrites every entry of a collection from `(K, V)` to `(K2, V2)` with `f`
+  /// and records the new types, all in one transaction. Returns the new schema
+  /// version, one more than before; check `collection_version` first to chain
+  /// migrations. Expired entries are dropped, and the others lose their expiry
+  /// time. The indexes of the collection are dropped as well.
+  pub fn migrate_collection<K, V, K2, V2>(&self, name: &str, f: impl FnMut((K, V)) -> (K2, V2)) -> Result<u32, Error>
+  where
+    K: Eq + serde::Serialize + serde::de::DeserializeOwned,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+    K2: Eq + serde::Serialize + serde::de::DeserializeOwned,
+    V2: serde::Serialize + serde::de::DeserializeOwned,
+  {
+    self.migrate(name, None, f)
+  }
+
+  /// Like `migrate_collection`, for a collection whose values use `codec`, as
+  /// set with `CollectionBuilder::codec`. Needed for codecs other than the
+  /// built-in ones. The migrated values keep using `codec`.
+  pub fn migrate_collection_with_codec<K, V, K2, V2, C>(
+    &self,
+    name: &str,
+    _codec: C,
+    f: impl FnMut((K, V)) -> (K2, V2),
+  ) -> Result<u32, Error>
+  where
+    K: Eq + serde::Serialize + serde::de::DeserializeOwned,
+    V: serde::Serialize + serde::de::DeserializeOwned,
+    K2: Eq + serde::Serialize + serde::de::DeserializeOwned,
+    V2: serde::Serialize + serde::de::DeserializeOwned,
+    C: Codec,
+  {
+    self.migrate(name, Some((ValueCodec::of::<C>(), ValueCodec::of::<C>())), f)
   }
 
-  pub fn get_collection<K, V>(&mut self, name: &str) -> Result<Collection<K, V>, Error>
+  /// Runs a migration, with the codecs of the old and new values if they are
+  /// not to be looked up by the recorded name.
+  fn migrate<K, V, K2, V2>(
+    &self,
+    name: &str,
+    codecs: Option<(ValueCodec<V>, ValueCodec<V2>)>,
+    mut f: impl FnMut((K, V)) -> (K2, V2),
+  ) -> Result<u32, Error>
   where
     K: Eq + serde::Serialize + serde::de::DeserializeOwned,
     V: serde::Serialize + serde::de::DeserializeOwned,
+    K2: Eq + serde::Serialize + serde::de::DeserializeOwned,
+    V2: serde::Serialize + serde::de::DeserializeOwned,
+  {
+    let tx = Txn::write(&self.pool)?;
+    ensure_exists(&tx, name)?;
+    let settings = CollectionSettings { codec: codecs.map(|(old, _)| old), ..Default::default() };
+    let codec = register_collection::<K, V>(&tx, name, &settings, tx.cipher())?;
+    let mut new_codec = match codecs {
+      Some((_, new)) => new,
+      None => ValueCodec::<V2>::builtin(codec.name)?,
+    };
+    new_codec.framing = codec.framing;
+
+    // New entries are staged under another name so they cannot clash with the old ones.
+    let staging = format!("{name}\0migrating");
+    {
+      let old = CollectionView::<K, V>::view(&tx, name.to_string(), codec);
+      let mut new = CollectionView::<K2, V2>::view(&tx, staging.clone(), new_codec);
+      for entry in old.iter() {
+        let (key, value) = f(entry?);
+        new.put(key, value)?;
+      }
+    }
+    tx.execute("DELETE FROM kv_store WHERE collection = ?", [name])?;
+    tx.execute("UPDATE kv_store SET collection = ? WHERE collection = ?", [name, &staging])?;
+    tx.execute("DELETE FROM kv_index WHERE collection = ?", [name])?;
+    tx.execute("DELETE FROM index_meta WHERE collection = ?", [name])?;
+    index::changed(&tx)?;
+
+    let version = tx.query_row(
+      "UPDATE collection_meta SET key_type = ?, value_type = ?, key_schema = ?, value_schema = ?, version = version + 1
+       WHERE name = ? RETURNING version",
+      rusqlite::params![
+        type_name::<K2>(),
+        type_name::<V2>(),
+        schema::fingerprint::<K2>(),
+        schema::fingerprint::<V2>(),
+        name,
+      ],
+      |row| row.get(0),
+    )?;
+    tx.commit()?;
+    self.pool.forget_indexes(name);
+    Ok(version)
+  }
+
+  /// Deletes a collection and all of its entries.
+  pub fn drop_collection(&self, name: &str) -> Result<(), Error> {
+    let tx = Txn::write(&self.pool)?;
+    ensure_exists(&tx, name)?;
+    tx.execute("DELETE FROM kv_store WHERE collection = ?", [name])?;
+    tx.execute("DELETE FROM kv_index WHERE collection = ?", [name])?;
+    tx.execute("DELETE FROM index_meta WHERE collection = ?", [name])?;
+    index::changed(&tx)?;
+    tx.execute("DELETE FROM collection_meta WHERE name = ?", [name])?;
+    tx.commit()?;
+    self.pool.forget_indexes(name);
+    Ok(())
+  }
+
+  /// Renames a collection. Fails if `to` already exists.
+  pub fn rename_collection(&self, from: &str, to: &str) -> Result<(), Error> {
+    let tx = Txn::write(&self.pool)?;
+    ensure_exists(&tx, from)?;
+    ensure_absent(&tx, to)?;
+    tx.execute("UPDATE kv_store SET collection = ? WHERE collection = ?", [to, from])?;
+    tx.execute("UPDATE kv_index SET collection = ? WHERE collection = ?", [to, from])?;
+    tx.execute("UPDATE index_meta SET collection = ? WHERE collection = ?", [to, from])?;
+    index::changed(&tx)?;
+    tx.execute("UPDATE collection_meta SET name = ? WHERE name = ?", [to, from])?;
+    tx.commit()?;
+    self.pool.copy_indexes(from, to);
+    self.pool.forget_indexes(from);
+    Ok(())
+  }
+
+  /// Copies a collection and all of its entries to a new collection `to`.
+  pub fn copy_collection(&self, from: &str, to: &str) -> Result<(), Error> {
+    let tx = Txn::write(&self.pool)?;
+    ensure_exists(&tx, from)?;
+    ensure_absent(&tx, to)?;
+    copy_rows(&tx, "collection_meta", "name", from, to)?;
+    copy_rows(&tx, "kv_store", "collection", from, to)?;
+    copy_rows(&tx, "kv_index", "collection", from, to)?;
+    copy_rows(&tx, "index_meta", "collection", from, to)?;
+    index::changed(&tx)?;
+    tx.commit()?;
+    self.pool.copy_indexes(from, to);
+    Ok(())
+  }
+
+  /// Deletes an index of a collection. Unlike `Collection::drop_index`, this
+  /// does not open the collection, so it also removes an index whose function
+  /// can no longer be registered.
+  pub fn drop_index(&self, collection: &str, name: &str) -> Result<(), Error> {
+    crate::collection::drop_index(&self.pool, collection, name)
+  }
+
+  /// Deletes every expired entry, returning how many were deleted. Expired
+  /// entries are already invisible to reads; this only reclaims their space.
+  pub fn purge_expired(&self) -> Result<usize, Error> {
+    expiry::purge(&self.pool)
+  }
+
+  /// Starts a thread that runs `purge_expired` every `interval`, until the
+  /// returned sweeper is dropped or the database is closed.
+  pub fn start_expiry_sweeper(&self, interval: Duration) -> Result<ExpirySweeper, Error> {
+    ExpirySweeper::start(Arc::downgrade(&self.pool), interval)
+  }
+
+  /// Re-encrypts every entry of an encrypted database with `key`, which must be
+  /// used to open the database from then on. Other handles open on the
+  /// database fail with `Error::WrongEncryptionKey` until reopened with `key`.
+  #[cfg(feature = "encryption")]
+  pub fn rotate_encryption_key(&self, key: crate::EncryptionKey) -> Result<(), Error> {
+    let tx = Txn::write(&self.pool)?;
+    let Some(old) = tx.cipher() else {
+      return Err(Error::EncryptionError("database is not encrypted".into()));
+    };
+    let new = Cipher::new(&key, old.encrypts_keys())?;
+    encryption::reencrypt(&tx, Some(old), &new)?;
+    self.pool.commit_with_cipher(tx, new)
+  }
+
+  /// Starts a transaction spanning any number of collections. Like
+  /// `Collection::begin`, this waits for other write transactions to finish.
+  pub fn begin(&self) -> Result<DatabaseTx<'_>, Error> {
+    let tx = Txn::write(&self.pool)?;
+    Ok(DatabaseTx::new(tx))
+  }
+
+  /// Runs `f` in a database-wide transaction that is committed if `f` returns
+  /// `Ok` and rolled back otherwise. Busy and locked errors are retried with
+  /// the default `RetryPolicy`.
+  pub fn transaction<T, F>(&self, f: F) -> Result<T, Error>
+  where
+    F: FnMut(&DatabaseTx<'_>) -> Result<T, Error>,
   {
-    let expected_key_type = type_name::<K>().to_string();
-    let expected_value_type = type_name::<V>().to_string();
-
-    let mut stmt = self.conn.prepare("SELECT key_type, value_type FROM collection_meta WHERE name = ?")?;
-    let mut rows = stmt.query([name])?;
-
-    if let Some(row) = rows.next()? {
-      let db_key_type: String = row.get(0)?;
-      let db_value_type: String = row.get(1)?;
-      if db_key_type != expected_key_type || db_value_type != expected_value_type {
-        return Err(Error::TypeMismatch {
-          expected_key: expected_key_type,
-          expected_value: expected_value_type,
-          got_key: db_key_type,
-          got_value: db_value_type,
-        });
+    self.transaction_with(&RetryPolicy::default(), f)
+  }
+
+  /// Like `transaction`, with an explicit retry policy.
+  pub fn transaction_with<T, F>(&self, policy: &RetryPolicy, mut f: F) -> Result<T, Error>
+  where
+    F: FnMut(&DatabaseTx<'_>) -> Result<T, Error>,
+  {
+    retry(policy, || {
+      let tx = self.begin()?;
+      match f(&tx) {
+        Ok(value) => {
+          tx.commit()?;
+          Ok(value)
+        }
+        Err(e) => {
+          tx.rollback()?;
+          Err(e)
+        }
       }
-    } else {
-      self.conn.execute(
-        "INSERT INTO collection_meta (name, key_type, value_type) VALUES (?, ?, ?)",
-        [name, &expected_key_type, &expected_value_type],
+    })
+  }
+}
+
+/// The `collection_meta` row of a collection, as checked by `register_collection`.
+struct StoredMeta {
+  key_type: String,
+  value_type: String,
+  key_schema: Option<String>,
+  value_schema: Option<String>,
+  key_encoding: i64,
+  codec: String,
+  compression: String,
+  compression_threshold: usize,
+}
+
+/// Checks the stored types of a collection against `K` and `V`, recording them
+/// if the collection is new. Types are compared by schema fingerprint, falling
+/// back to their names when a fingerprint is not available.
+///
+/// Returns the value codec of the collection. A codec given in `settings` must
+/// match the recorded one, while a change of compression is applied to the
+/// stored values.
+pub(crate) fn register_collection<K, V>(
+  conn: &Connection,
+  name: &str,
+  settings: &CollectionSettings<V>,
+  cipher: Option<&Cipher>,
+) -> Result<ValueCodec<V>, Error>
+where
+  K: serde::Serialize + serde::de::DeserializeOwned,
+  V: serde::Serialize + serde::de::DeserializeOwned,
+{
+  let expected_key_type = type_name::<K>().to_string();
+  let expected_value_type = type_name::<V>().to_string();
+  let key_schema = schema::fingerprint::<K>();
+  let value_schema = schema::fingerprint::<V>();
+
+  let existing = conn.prepare_cached(
+    "SELECT key_type, value_type, key_schema, value_schema, key_encoding, codec, compression, compression_threshold
+     FROM collection_meta WHERE name = ?",
+  )?.query_row(
+    [name],
+    |row| Ok(StoredMeta {
+      key_type: row.get(0)?,
+      value_type: row.get(1)?,
+      key_schema: row.get(2)?,
+      value_schema: row.get(3)?,
+      key_encoding: row.get(4)?,
+      codec: row.get(5)?,
+      compression: row.get(6)?,
+      compression_threshold: row.get(7)?,
+    }),
+  ).optional()?;
+
+  if let Some(stored) = existing {
+    if !same_type(&stored.key_type, &stored.key_schema, &expected_key_type, &key_schema)
+      || !same_type(&stored.value_type, &stored.value_schema, &expected_value_type, &value_schema)
+    {
+      return Err(Error::TypeMismatch {
+        expected_key: expected_key_type,
+        expected_value: expected_value_type,
+        got_key: stored.key_type,
+        got_value: stored.value_type,
+      });
+    }
+    let mut codec = match settings.codec {
+      Some(codec) if codec.name != stored.codec => {
+        return Err(Error::CodecMismatch { expected: codec.name.to_string(), got: stored.codec });
+      }
+      Some(codec) => codec,
+      None => ValueCodec::builtin(&stored.codec)?,
+    };
+    let recorded = Framing {
+      compression: Compression::from_name(&stored.compression)?,
+      threshold: stored.compression_threshold,
+    };
+    codec.framing = Framing {
+      compression: settings.compression.unwrap_or(recorded.compression),
+      threshold: settings.compression_threshold.unwrap_or(recorded.threshold),
+    };
+    if codec.framing != recorded {
+      with_savepoint(conn, |conn| reframe_values(conn, name, &recorded, &codec, cipher))?;
+    }
+    let renamed = stored.key_type != expected_key_type || stored.value_type != expected_value_type;
+    let fingerprinted = (stored.key_schema.is_none() && key_schema.is_some())
+      || (stored.value_schema.is_none() && value_schema.is_some());
+    if (renamed || fingerprinted) && !conn.is_readonly(rusqlite::DatabaseName::Main)? {
+      conn.execute(
+        "UPDATE collection_meta SET key_type = ?, value_type = ?, key_schema = COALESCE(?, key_schema),
+         value_schema = COALESCE(?, value_schema) WHERE name = ?",
+        rusqlite::params![&expected_key_type, &expected_value_type, &key_schema, &value_schema, name],
       )?;
     }
+    if stored.key_encoding == KEY_ENCODING_POSTCARD {
+      if conn.is_readonly(rusqlite::DatabaseName::Main)? {
+        return Err(Error::UpgradeRequired);
+      }
+      with_savepoint(conn, |conn| migrate_key_encoding::<K>(conn, name, cipher))?;
+    }
+    Ok(codec)
+  } else {
+    let mut codec = settings.codec.unwrap_or_else(ValueCodec::of::<Postcard>);
+    codec.framing = Framing {
+      compression: settings.compression.unwrap_or(Compression::None),
+      threshold: settings.compression_threshold.unwrap_or(compression::DEFAULT_THRESHOLD),
+    };
+    conn.execute(
+      "INSERT INTO collection_meta (name, key_type, value_type, key_schema, value_schema, key_encoding, codec,
+         compression, compression_threshold)
+       VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+      rusqlite::params![
+        name,
+        &expected_key_type,
+        &expected_value_type,
+        &key_schema,
+        &value_schema,
+        KEY_ENCODING_ORDERED,
+        codec.name,
+        codec.framing.compression.name(),
+        codec.framing.threshold,
+      ],
+    )?;
+    Ok(codec)
+  }
+}
+
+/// Compares a stored type with an expected one, by fingerprint if both sides
+/// have one and by name otherwise.
+fn same_type(stored_name: &str, stored_schema: &Option<String>, name: &str, schema: &Option<String>) -> bool {
+  match (stored_schema, schema) {
+    (Some(stored), Some(expected)) => stored == expected,
+    _ => stored_name == name,
+  }
+}
+
+/// Records a new compression setting. Values are rewritten only if they gain
+/// or lose their header byte; otherwise the header of each value still tells
+/// how to read it.
+fn reframe_values<V>(
+  conn: &Connection,
+  name: &str,
+  old: &Framing,
+  codec: &ValueCodec<V>,
+  cipher: Option<&Cipher>,
+) -> Result<(), Error> {
+  if old.framed() != codec.framing.framed() {
+    let mut update = conn.prepare("UPDATE kv_store SET value = ? WHERE collection = ? AND key = ?")?;
+    for_each_row(conn, name, |stored_key, stored| {
+      let key = encryption::open_key(cipher, stored_key.clone())?;
+      let stored = encryption::open_value(cipher, &key, stored)?;
+      let value = encryption::seal_value(cipher, &key, codec.reframe(old, &stored)?)?;
+      update.execute(rusqlite::params![&value, name, &stored_key])?;
+      Ok(())
+    })?;
+  }
+  conn.execute(
+    "UPDATE collection_meta SET compression = ?, compression_threshold = ? WHERE name = ?",
+    rusqlite::params![codec.framing.compression.name(), codec.framing.threshold, name],
+  )?;
+  Ok(())
+}
+
+fn collection_exists(conn: &Connection, name: &str) -> Result<bool, Error> {
+  Ok(conn.prepare("SELECT 1 FROM collection_meta WHERE name = ?")?.exists([name])?)
+}
+
+fn ensure_exists(conn: &Connection, name: &str) -> Result<(), Error> {
+  if !collection_exists(conn, name)? {
+    return Err(Error::CollectionNotFound(name.to_string()));
+  }
+  Ok(())
+}
+
+fn ensure_absent(conn: &Connection, name: &str) -> Result<(), Error> {
+  if collection_exists(conn, name)? {
+    return Err(Error::CollectionExists(name.to_string()));
+  }
+  Ok(())
+}
+
+/// Duplicates the rows of `table` whose `column` equals `from`, setting it to `to`.
+/// Columns are read from the schema so that every column is carried over.
+fn copy_rows(conn: &Connection, table: &str, column: &str, from: &str, to: &str) -> Result<(), Error> {
+  let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?)")?;
+  let columns = stmt.query_map([table], |row| row.get::<_, String>(0))?.collect::<Result<Vec<_>, _>>()?;
+  let select: Vec<&str> = columns.iter().map(|c| if c == column { "?1" } else { c.as_str() }).collect();
+  let sql = format!(
+    "INSERT INTO {table} ({}) SELECT {} FROM {table} WHERE {column} = ?2",
+    columns.join(", "),
+    select.join(", "),
+  );
+  conn.execute(&sql, [to, from])?;
+  Ok(())
+}
+
+/// Runs `f` inside a savepoint, so it is atomic whether or not a transaction is
+/// already open on `conn`.
+pub(crate) fn with_savepoint<T>(conn: &Connection, f: impl FnOnce(&Connection) -> Result<T, Error>) -> Result<T, Error> {
+  conn.execute_batch("SAVEPOINT storedb")?;
+  match f(conn) {
+    Ok(value) => {
+      conn.execute_batch("RELEASE storedb")?;
+      Ok(value)
+    }
+    Err(e) => {
+      conn.execute_batch("ROLLBACK TO storedb; RELEASE storedb")?;
+      Err(e)
+    }
+  }
+}
+
+/// Applies the missing schema upgrades. The version is read again once the
+/// write lock is taken, since another connection may have upgraded the
+/// database in the meantime.
+fn upgrade_schema(conn: &Connection) -> Result<(), Error> {
+  if schema_version(conn)? == SCHEMA_UPGRADES.len() {
+    return Ok(());
+  }
+  let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+  let version = schema_version(&tx)?;
+  for (i, sql) in SCHEMA_UPGRADES.iter().enumerate().skip(version) {
+    tx.execute_batch(sql)?;
+    tx.pragma_update(None, "user_version", i + 1)?;
+  }
+  tx.commit()?;
+  Ok(())
+}
+
+/// Number of `SCHEMA_UPGRADES` applied to the database.
+fn schema_version(conn: &Connection) -> Result<usize, Error> {
+  Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
 
-    Ok(Collection::new(self.conn.clone(), name.to_string()))
+/// Rewrites the keys of a collection created before keys were stored in
+/// order-preserving form.
+fn migrate_key_encoding<K>(conn: &Connection, name: &str, cipher: Option<&Cipher>) -> Result<(), Error>
+where
+  K: serde::Serialize + serde::de::DeserializeOwned,
+{
+  // Rewritten entries are staged under another name so they cannot clash with
+  // the keys not rewritten yet.
+  let staging = format!("{name}\0rekeying");
+  let mut update = conn.prepare(
+    "UPDATE kv_store SET collection = ?, key = ?, value = ? WHERE collection = ? AND key = ?",
+  )?;
+  for_each_row(conn, name, |stored_key, stored| {
+    let key_bytes = encryption::open_key(cipher, stored_key.clone())?;
+    let value = encryption::open_value(cipher, &key_bytes, stored)?;
+    let key: K = postcard::from_bytes(&key_bytes)?;
+    let key_bytes = key_codec::encode_key(&key)?;
+    let value = encryption::seal_value(cipher, &key_bytes, value.into())?;
+    let key_bytes = encryption::seal_key(cipher, key_bytes)?;
+    update.execute(rusqlite::params![&staging, &key_bytes, &value, name, &stored_key])?;
+    Ok(())
+  })?;
+  conn.execute("UPDATE kv_store SET collection = ? WHERE collection = ?", [name, &staging])?;
+  conn.execute(
+    "UPDATE collection_meta SET key_encoding = ? WHERE name = ?",
+    rusqlite::params![KEY_ENCODING_ORDERED, name],
+  )?;
+  Ok(())
+}
+
+/// Calls `f` with the stored key and value of each entry of a collection,
+/// expired or not, in key order. Rows are read in batches, so memory use does
+/// not grow with the size of the collection. `f` may update or move the entry
+/// it is given, but not create entries in the collection.
+fn for_each_row(
+  conn: &Connection,
+  name: &str,
+  mut f: impl FnMut(Vec<u8>, StoredBytes) -> Result<(), Error>,
+) -> Result<(), Error> {
+  let mut first = conn.prepare("SELECT key, value FROM kv_store WHERE collection = ?1 ORDER BY key LIMIT ?2")?;
+  let mut next = conn.prepare("SELECT key, value FROM kv_store WHERE collection = ?1 AND key > ?3 ORDER BY key LIMIT ?2")?;
+  let read_row = |row: &rusqlite::Row<'_>| Ok((row.get(0)?, row.get(1)?));
+  let mut batch: Vec<(Vec<u8>, StoredBytes)> =
+    first.query_map(rusqlite::params![name, UPGRADE_BATCH], read_row)?.collect::<Result<_, _>>()?;
+  while let Some(last) = batch.last().map(|(key, _)| key.clone()) {
+    for (key, value) in batch {
+      f(key, value)?;
+    }
+    batch = next.query_map(rusqlite::params![name, UPGRADE_BATCH, last], read_row)?.collect::<Result<_, _>>()?;
   }
+  Ok(())
 }
@@ -0,0 +1,67 @@
+use serde::{Serialize, de::DeserializeOwned};
+use crate::Error;
+use crate::codec::{Codec, ValueCodec};
+use crate::collection::CollectionSettings;
+use crate::collection_tx::{CollectionTx, CollectionView};
+use crate::database::register_collection;
+use crate::index;
+use crate::pool::Txn;
+
+/// A transaction over the whole database. Every collection view it hands out
+/// commits or rolls back together.
+pub struct DatabaseTx<'a> {
+  tx: Txn<'a>,
+}
+
+impl<'a> DatabaseTx<'a> {
+  pub(crate) fn new(tx: Txn<'a>) -> Self {
+    DatabaseTx { tx }
+  }
+
+  /// Returns a typed view of the named collection, creating it if needed. Like
+  /// `CollectionBuilder::open`, this fails with `Error::IndexNotRegistered` if
+  /// one of its indexes was not registered in this process.
+  pub fn collection<K, V>(&self, name: &str) -> Result<CollectionView<'_, K, V>, Error>
+  where
+    K: Eq + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+  {
+    self.open_view(name, &CollectionSettings::default())
+  }
+
+  /// Like `collection`, for a collection whose values use `codec`, as set with
+  /// `CollectionBuilder::codec`. Needed for codecs other than the built-in ones.
+  pub fn collection_with_codec<K, V, C>(&self, name: &str, _codec: C) -> Result<CollectionView<'_, K, V>, Error>
+  where
+    K: Eq + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+  {
+    let settings = CollectionSettings { codec: Some(ValueCodec::of::<C>()), ..Default::default() };
+    self.open_view(name, &settings)
+  }
+
+  fn open_view<K, V>(&self, name: &str, settings: &CollectionSettings<V>) -> Result<CollectionView<'_, K, V>, Error>
+  where
+    K: Eq + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+  {
+    let codec = register_collection::<K, V>(&self.tx, name, settings, self.tx.cipher())?;
+    index::ensure_registered(&self.tx.indexes(name)?)?;
+    Ok(CollectionTx::view(&self.tx, name.to_string(), codec))
+  }
+
+  pub fn cancel(self) -> Result<(), Error> {
+    self.rollback()
+  }
+
+  pub fn rollback(self) -> Result<(), Error> {
+    self.tx.rollback()?;
+    Ok(())
+  }
+
+  pub fn commit(self) -> Result<(), Error> {
+    self.tx.commit()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,390 @@
+//! Encryption of stored values and, optionally, keys.
+//!
+//! Values are sealed with XChaCha20-Poly1305 under a random nonce, with their
+//! encoded key as associated data so that a value cannot be moved to another
+//! key. Keys are sealed with AES-256-GCM-SIV under a fixed nonce: a key always
+//! encrypts to the same bytes, so lookups still work, but key order is lost.
+//! Index keys are derived from values, so they are sealed the same way even
+//! when entry keys are not. Both ciphers use subkeys derived from the
+//! `EncryptionKey` with HMAC-SHA256.
+//!
+//! `database_meta` holds a value sealed with the key, so a wrong key is caught
+//! on open, and whether keys are encrypted. Each transaction compares that
+//! value with the one its handle opened, so a handle left behind by a key
+//! rotation in another handle fails instead of writing with the old key.
+
+use rusqlite::{Connection, OptionalExtension};
+use crate::Error;
+use crate::codec::{Encoded, StoredBytes};
+use crate::options::DatabaseOptions;
+
+#[cfg(feature = "encryption")]
+use aes_gcm_siv::Aes256GcmSiv;
+#[cfg(feature = "encryption")]
+use chacha20poly1305::XChaCha20Poly1305;
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload, rand_core::RngCore};
+
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 24;
+#[cfg(feature = "encryption")]
+const CHECK: &[u8] = b"storedb";
+/// Rows read per query while re-encrypting.
+#[cfg(feature = "encryption")]
+const REENCRYPT_BATCH: usize = 512;
+
+/// A 256-bit key for encrypting a database, see `DatabaseOptions::encryption_key`.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+#[cfg(feature = "encryption")]
+impl EncryptionKey {
+  pub fn new(bytes: [u8; 32]) -> Self {
+    EncryptionKey(bytes)
+  }
+
+  /// Generates a random key with the operating system's RNG.
+  pub fn generate() -> Self {
+    let mut bytes = [0; 32];
+    OsRng.fill_bytes(&mut bytes);
+    EncryptionKey(bytes)
+  }
+
+  fn derive(&self, label: &[u8]) -> [u8; 32] {
+    use hmac::{Hmac, Mac};
+    let mut mac = <Hmac<sha2::Sha256> as Mac>::new_from_slice(&self.0).expect("HMAC takes keys of any length");
+    mac.update(label);
+    mac.finalize().into_bytes().into()
+  }
+}
+
+#[cfg(feature = "encryption")]
+impl From<[u8; 32]> for EncryptionKey {
+  fn from(bytes: [u8; 32]) -> Self {
+    EncryptionKey(bytes)
+  }
+}
+
+#[cfg(feature = "encryption")]
+impl std::fmt::Debug for EncryptionKey {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("EncryptionKey(..)")
+  }
+}
+
+/// The ciphers of an encrypted database.
+#[cfg(feature = "encryption")]
+pub(crate) struct Cipher {
+  values: XChaCha20Poly1305,
+  /// Seals index keys, and entry keys if `encrypt_keys` is set.
+  keys: Aes256GcmSiv,
+  encrypt_keys: bool,
+  /// The check value recorded in `database_meta` for this key, see `verify`.
+  check: Vec<u8>,
+}
+
+#[cfg(feature = "encryption")]
+impl Cipher {
+  /// Ciphers for `key`, with a new check value.
+  pub(crate) fn new(key: &EncryptionKey, encrypt_keys: bool) -> Result<Self, Error> {
+    let mut cipher = Cipher {
+      values: XChaCha20Poly1305::new(&key.derive(b"storedb values").into()),
+      keys: Aes256GcmSiv::new(&key.derive(b"storedb keys").into()),
+      encrypt_keys,
+      check: Vec::new(),
+    };
+    cipher.check = cipher.seal_value(CHECK, Encoded { bytes: CHECK.to_vec(), text: false })?.bytes;
+    Ok(cipher)
+  }
+
+  fn check(&self) -> &[u8] {
+    &self.check
+  }
+
+  pub(crate) fn encrypts_keys(&self) -> bool {
+    self.encrypt_keys
+  }
+
+  /// Encrypts a value stored under the encoded key `key`. The result is always
+  /// a BLOB; whether the value was text is kept in its first plaintext byte.
+  fn seal_value(&self, key: &[u8], value: Encoded) -> Result<Encoded, Error> {
+    let mut msg = Vec::with_capacity(value.bytes.len() + 1);
+    msg.push(value.text as u8);
+    msg.extend_from_slice(&value.bytes);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = self.values.encrypt(&nonce, Payload { msg: &msg, aad: key })
+      .map_err(|_| Error::EncryptionError("could not encrypt value".into()))?;
+    let mut bytes = nonce.to_vec();
+    bytes.extend_from_slice(&sealed);
+    Ok(Encoded { bytes, text: false })
+  }
+
+  fn open_value(&self, key: &[u8], stored: &StoredBytes) -> Result<StoredBytes, Error> {
+    if stored.text || stored.bytes.len() < NONCE_LEN {
+      return Err(Error::EncryptionError("value is not encrypted".into()));
+    }
+    let (nonce, sealed) = stored.bytes.split_at(NONCE_LEN);
+    let mut msg = self.values.decrypt(nonce.into(), Payload { msg: sealed, aad: key })
+      .map_err(|_| Error::EncryptionError("value could not be decrypted".into()))?;
+    match msg.first() {
+      Some(&flag) if flag <= 1 => {
+        msg.remove(0);
+        Ok(StoredBytes { bytes: msg, text: flag == 1 })
+      }
+      _ => Err(Error::EncryptionError("malformed value".into())),
+    }
+  }
+
+  fn seal_key(&self, key: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match self.encrypt_keys {
+      true => self.seal_index_key(&key),
+      false => Ok(key),
+    }
+  }
+
+  fn open_key(&self, stored: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match self.encrypt_keys {
+      true => self.open_index_key(&stored),
+      false => Ok(stored),
+    }
+  }
+
+  fn seal_index_key(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+    self.keys.encrypt(&Default::default(), key)
+      .map_err(|_| Error::EncryptionError("could not encrypt key".into()))
+  }
+
+  fn open_index_key(&self, stored: &[u8]) -> Result<Vec<u8>, Error> {
+    self.keys.decrypt(&Default::default(), stored)
+      .map_err(|_| Error::EncryptionError("key could not be decrypted".into()))
+  }
+}
+
+/// Stands in for the ciphers when the `encryption` feature is off, where no
+/// database is opened with one.
+#[cfg(not(feature = "encryption"))]
+pub(crate) enum Cipher {}
+
+#[cfg(not(feature = "encryption"))]
+impl Cipher {
+  pub(crate) fn encrypts_keys(&self) -> bool {
+    match *self {}
+  }
+
+  fn check(&self) -> &[u8] {
+    match *self {}
+  }
+
+  fn seal_value(&self, _key: &[u8], _value: Encoded) -> Result<Encoded, Error> {
+    match *self {}
+  }
+
+  fn open_value(&self, _key: &[u8], _stored: &StoredBytes) -> Result<StoredBytes, Error> {
+    match *self {}
+  }
+
+  fn seal_key(&self, _key: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match *self {}
+  }
+
+  fn open_key(&self, _stored: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match *self {}
+  }
+
+  fn seal_index_key(&self, _key: &[u8]) -> Result<Vec<u8>, Error> {
+    match *self {}
+  }
+}
+
+/// Encrypts a value for storage under the encoded key `key`, if the database is encrypted.
+pub(crate) fn seal_value(cipher: Option<&Cipher>, key: &[u8], value: Encoded) -> Result<Encoded, Error> {
+  match cipher {
+    Some(cipher) => cipher.seal_value(key, value),
+    None => Ok(value),
+  }
+}
+
+/// Decrypts a value stored under the encoded key `key`, if the database is encrypted.
+pub(crate) fn open_value(cipher: Option<&Cipher>, key: &[u8], stored: StoredBytes) -> Result<StoredBytes, Error> {
+  match cipher {
+    Some(cipher) => cipher.open_value(key, &stored),
+    None => Ok(stored),
+  }
+}
+
+/// Turns an encoded key into the form stored in `kv_store`.
+pub(crate) fn seal_key(cipher: Option<&Cipher>, key: Vec<u8>) -> Result<Vec<u8>, Error> {
+  match cipher {
+    Some(cipher) => cipher.seal_key(key),
+    None => Ok(key),
+  }
+}
+
+/// Recovers an encoded key from the form stored in `kv_store`.
+pub(crate) fn open_key(cipher: Option<&Cipher>, stored: Vec<u8>) -> Result<Vec<u8>, Error> {
+  match cipher {
+    Some(cipher) => cipher.open_key(stored),
+    None => Ok(stored),
+  }
+}
+
+/// Turns an encoded index key into the form stored in `kv_index`. Unlike entry
+/// keys, index keys are encrypted whenever the database is.
+pub(crate) fn seal_index_key(cipher: Option<&Cipher>, key: Vec<u8>) -> Result<Vec<u8>, Error> {
+  match cipher {
+    Some(cipher) => cipher.seal_index_key(&key),
+    None => Ok(key),
+  }
+}
+
+/// Recovers an encoded index key from the form stored in `kv_index`.
+#[cfg(feature = "encryption")]
+fn open_index_key(cipher: Option<&Cipher>, stored: Vec<u8>) -> Result<Vec<u8>, Error> {
+  match cipher {
+    Some(cipher) => cipher.open_index_key(&stored),
+    None => Ok(stored),
+  }
+}
+
+/// Whether stored keys are encrypted, and so unordered.
+pub(crate) fn encrypts_keys(cipher: Option<&Cipher>) -> bool {
+  cipher.is_some_and(Cipher::encrypts_keys)
+}
+
+/// The encryption recorded for a database: its check value, and whether keys
+/// are encrypted. `None` if the database is not encrypted.
+fn recorded(conn: &Connection) -> Result<Option<(Vec<u8>, bool)>, Error> {
+  let has_meta = conn.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'database_meta'")?
+    .exists([])?;
+  if !has_meta {
+    return Ok(None);
+  }
+  let check: Option<Vec<u8>> = conn.query_row(
+    "SELECT value FROM database_meta WHERE name = 'encryption_check'",
+    [],
+    |row| row.get(0),
+  ).optional()?;
+  let Some(check) = check else {
+    return Ok(None);
+  };
+  let keys: i64 = conn.query_row("SELECT value FROM database_meta WHERE name = 'encrypted_keys'", [], |row| row.get(0))?;
+  Ok(Some((check, keys != 0)))
+}
+
+/// Fails if the database, as seen by `conn`, is not encrypted the way `cipher`
+/// expects: with `Error::WrongEncryptionKey` after another handle rotated the
+/// key, or `Error::EncryptionKeyRequired` after another handle encrypted it.
+pub(crate) fn verify(conn: &Connection, cipher: Option<&Cipher>) -> Result<(), Error> {
+  let mut stmt = conn.prepare_cached("SELECT value FROM database_meta WHERE name = 'encryption_check'")?;
+  let check: Option<Vec<u8>> = stmt.query_row([], |row| row.get(0)).optional()?;
+  match (cipher, check) {
+    (None, None) => Ok(()),
+    (None, Some(_)) => Err(Error::EncryptionKeyRequired),
+    (Some(cipher), Some(check)) if cipher.check() == check => Ok(()),
+    (Some(_), _) => Err(Error::WrongEncryptionKey),
+  }
+}
+
+/// Checks the key in `options` against the encryption recorded for the
+/// database, and returns the cipher to use. An unencrypted database opened
+/// with a key is encrypted in place.
+#[cfg(feature = "encryption")]
+pub(crate) fn open_cipher(conn: &Connection, options: &DatabaseOptions) -> Result<Option<Cipher>, Error> {
+  let Some(key) = &options.encryption_key else {
+    return match recorded(conn)? {
+      Some(_) => Err(Error::EncryptionKeyRequired),
+      None => Ok(None),
+    };
+  };
+  match recorded(conn)? {
+    Some((check, encrypt_keys)) => {
+      if encrypt_keys != options.encrypt_keys {
+        let state = if encrypt_keys { "encrypted" } else { "not encrypted" };
+        return Err(Error::EncryptionError(format!("keys in this database are {state}")));
+      }
+      let cipher = Cipher { check: check.clone(), ..Cipher::new(key, encrypt_keys)? };
+      let stored = StoredBytes { bytes: check, text: false };
+      match cipher.open_value(CHECK, &stored) {
+        Ok(check) if check.bytes == CHECK => Ok(Some(cipher)),
+        _ => Err(Error::WrongEncryptionKey),
+      }
+    }
+    None if options.read_only => Err(Error::EncryptionError("database is not encrypted".into())),
+    None => {
+      let cipher = Cipher::new(key, options.encrypt_keys)?;
+      let tx = conn.unchecked_transaction()?;
+      reencrypt(&tx, None, &cipher)?;
+      tx.commit()?;
+      Ok(Some(cipher))
+    }
+  }
+}
+
+#[cfg(not(feature = "encryption"))]
+pub(crate) fn open_cipher(conn: &Connection, _options: &DatabaseOptions) -> Result<Option<Cipher>, Error> {
+  match recorded(conn)? {
+    Some(_) => Err(Error::EncryptionKeyRequired),
+    None => Ok(None),
+  }
+}
+
+/// Rewrites every entry of the database from `old` encryption to `new`, and
+/// records `new` in `database_meta`. Rows are read in batches, so memory use
+/// does not grow with the size of the database.
+#[cfg(feature = "encryption")]
+pub(crate) fn reencrypt(conn: &Connection, old: Option<&Cipher>, new: &Cipher) -> Result<(), Error> {
+  // Entries are updated in place, in rowid order, which updates keep. A newly
+  // sealed key clashing with one not rewritten yet is as unlikely as a forgery.
+  {
+    let mut select = conn.prepare("SELECT rowid, key, value FROM kv_store WHERE rowid > ? ORDER BY rowid LIMIT ?")?;
+    let mut update = conn.prepare("UPDATE kv_store SET key = ?, value = ? WHERE rowid = ?")?;
+    let mut after = i64::MIN;
+    loop {
+      let batch = select.query_map(rusqlite::params![after, REENCRYPT_BATCH], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+      })?.collect::<Result<Vec<(i64, Vec<u8>, StoredBytes)>, _>>()?;
+      let Some(last) = batch.last().map(|(rowid, _, _)| *rowid) else {
+        break;
+      };
+      for (rowid, stored_key, stored) in batch {
+        let key = open_key(old, stored_key)?;
+        let value = new.seal_value(&key, open_value(old, &key, stored)?.into())?;
+        update.execute(rusqlite::params![new.seal_key(key)?, value, rowid])?;
+      }
+      after = last;
+    }
+  }
+  // Index rows are ordered by the keys being rewritten, so they are copied to a
+  // temporary table, in primary key order, and then copied back.
+  conn.execute_batch("CREATE TEMP TABLE storedb_reencrypted (collection TEXT, name TEXT, index_key BLOB, key BLOB)")?;
+  {
+    let columns = "collection, name, index_key, key";
+    let mut first = conn.prepare(&format!("SELECT {columns} FROM kv_index ORDER BY {columns} LIMIT ?1"))?;
+    let mut next = conn.prepare(&format!(
+      "SELECT {columns} FROM kv_index WHERE ({columns}) > (?2, ?3, ?4, ?5) ORDER BY {columns} LIMIT ?1",
+    ))?;
+    let mut insert = conn.prepare(&format!("INSERT INTO temp.storedb_reencrypted ({columns}) VALUES (?, ?, ?, ?)"))?;
+    let read_row = |row: &rusqlite::Row<'_>| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?));
+    let mut batch: Vec<(String, String, Vec<u8>, Vec<u8>)> =
+      first.query_map([REENCRYPT_BATCH], read_row)?.collect::<Result<_, _>>()?;
+    while let Some((collection, name, index_key, key)) = batch.last().cloned() {
+      for (collection, name, index_key, key) in batch {
+        let index_key = new.seal_index_key(&open_index_key(old, index_key)?)?;
+        insert.execute(rusqlite::params![collection, name, index_key, new.seal_key(open_key(old, key)?)?])?;
+      }
+      batch = next.query_map(rusqlite::params![REENCRYPT_BATCH, collection, name, index_key, key], read_row)?
+        .collect::<Result<_, _>>()?;
+    }
+  }
+  conn.execute_batch(
+    "DELETE FROM kv_index;
+     INSERT INTO kv_index (collection, name, index_key, key) SELECT collection, name, index_key, key FROM temp.storedb_reencrypted;
+     DROP TABLE temp.storedb_reencrypted;",
+  )?;
+  conn.execute(
+    "INSERT OR REPLACE INTO database_meta (name, value) VALUES ('encryption_check', ?), ('encrypted_keys', ?)",
+    rusqlite::params![new.check(), new.encrypts_keys()],
+  )?;
+  Ok(())
+}
@@ -5,12 +5,88 @@ pub enum Error {
   #[error("SQLite error: {0}")]
   SqliteError(#[from] rusqlite::Error),
 
+  #[error("I/O error: {0}")]
+  IoError(#[from] std::io::Error),
+
   #[error("Serialization error: {0}")]
   SerializationError(#[from] postcard::Error),
 
+  #[error("Value codec error: {0}")]
+  CodecError(String),
+
+  #[error("Compression error: {0}")]
+  CompressionError(String),
+
+  #[error("Unknown compression: {0}")]
+  UnknownCompression(String),
+
+  #[error("Encryption error: {0}")]
+  EncryptionError(String),
+
+  #[error("The database is encrypted and no encryption key was given")]
+  EncryptionKeyRequired,
+
+  #[error("Wrong encryption key")]
+  WrongEncryptionKey,
+
+  #[error("Range and prefix queries are not available when keys are encrypted")]
+  KeyOrderUnavailable,
+
+  #[error("Key encoding error: {0}")]
+  KeyEncodingError(String),
+
+  #[error("The database was written by an older version of storedb; open it read-write once to upgrade it")]
+  UpgradeRequired,
+
+  #[error("Invalid page cursor")]
+  InvalidCursor,
+
+  #[error("Page limit must be at least 1")]
+  InvalidPageLimit,
+
+  #[error("This thread already has a write transaction open on the database")]
+  NestedTransaction,
+
+  #[error("Background worker stopped before completing the operation")]
+  WorkerStopped,
+
   #[error("Key being inserted already exists")]
   KeyAlreadyExists,
 
+  #[error("Condition on the current value failed")]
+  ConditionFailed {
+    /// The value found, boxed as the collection's value type; see `Error::current_value`.
+    current: Option<Box<dyn std::any::Any + Send + Sync>>,
+  },
+
+  #[error("Unique index {index} already has this key for another entry")]
+  UniqueViolation {
+    index: String,
+    /// Encoded key of the entry holding the index key; see `Error::conflicting_key`.
+    key: Vec<u8>,
+  },
+
+  #[error("Index not found: {0}")]
+  IndexNotFound(String),
+
+  #[error("Index {0} exists but was not registered in this process with `CollectionBuilder::index` or `create_index`")]
+  IndexNotRegistered(String),
+
+  #[error("Collection not found: {0}")]
+  CollectionNotFound(String),
+
+  #[error("Collection already exists: {0}")]
+  CollectionExists(String),
+
+  #[error("Unknown value codec: {0}")]
+  UnknownCodec(String),
+
+  #[error("Collection codec mismatch: expected {expected}, got {got}")]
+  CodecMismatch {
+    expected: String,
+    got: String,
+  },
+
   #[error("Collection type mismatch: expected key={expected_key}, value={expected_value}, got key={got_key}, value={got_value}")]
   TypeMismatch {
     expected_key: String,
@@ -19,3 +95,46 @@ pub enum Error {
     got_value: String,
   },
 }
+
+impl Error {
+  /// The value found by a failed `compare_and_swap` or `update_if`, or `None`
+  /// if the key was absent, the error is of another kind, or `V` is not the
+  /// collection's value type.
+  pub fn current_value<V: 'static>(&self) -> Option<&V> {
+    match self {
+      Error::ConditionFailed { current: Some(value) } => value.downcast_ref(),
+      _ => None,
+    }
+  }
+
+  /// The key of the entry that caused a `UniqueViolation`, or `None` for other
+  /// errors or if `K` is not the collection's key type.
+  pub fn conflicting_key<K: serde::de::DeserializeOwned>(&self) -> Option<K> {
+    match self {
+      Error::UniqueViolation { key, .. } => crate::key_codec::decode_key(key).ok(),
+      _ => None,
+    }
+  }
+
+  /// Whether SQLite reported the database as busy or locked, meaning the
+  /// operation may succeed if retried.
+  pub fn is_busy(&self) -> bool {
+    matches!(
+      self,
+      Error::SqliteError(rusqlite::Error::SqliteFailure(e, _))
+        if matches!(e.code, rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+  }
+}
+
+impl serde::ser::Error for Error {
+  fn custom<T: std::fmt::Display>(msg: T) -> Self {
+    Error::KeyEncodingError(msg.to_string())
+  }
+}
+
+impl serde::de::Error for Error {
+  fn custom<T: std::fmt::Display>(msg: T) -> Self {
+    Error::KeyEncodingError(msg.to_string())
+  }
+}
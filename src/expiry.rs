@@ -0,0 +1,58 @@
+//! Expiry of entries written with a time to live.
+//!
+//! `kv_store.expires_at` holds the time an entry expires, in milliseconds since
+//! the Unix epoch, or NULL if it does not. Reads skip expired entries, and
+//! `Database::purge_expired` deletes them.
+
+use std::sync::{Weak, mpsc};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::Error;
+use crate::pool::{Pool, Txn};
+
+/// The current time, as stored in `expires_at`.
+pub(crate) fn now() -> i64 {
+  let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+  i64::try_from(since_epoch.as_millis()).unwrap_or(i64::MAX)
+}
+
+/// The expiry time of an entry written now with time to live `ttl`.
+pub(crate) fn deadline(ttl: Duration) -> i64 {
+  now().saturating_add(i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX))
+}
+
+pub(crate) fn purge(pool: &Pool) -> Result<usize, Error> {
+  let tx = Txn::write(pool)?;
+  let now = now();
+  tx.prepare_cached(
+    "DELETE FROM kv_index WHERE (collection, key) IN (SELECT collection, key FROM kv_store WHERE expires_at <= ?)",
+  )?.execute([now])?;
+  let deleted = tx.prepare_cached("DELETE FROM kv_store WHERE expires_at <= ?")?.execute([now])?;
+  tx.commit()?;
+  Ok(deleted)
+}
+
+/// Runs `Database::purge_expired` on a background thread at a fixed interval,
+/// until dropped or the database is closed. Created with
+/// `Database::start_expiry_sweeper`.
+#[derive(Debug)]
+pub struct ExpirySweeper {
+  _stop: mpsc::Sender<()>,
+}
+
+impl ExpirySweeper {
+  pub(crate) fn start(pool: Weak<Pool>, interval: Duration) -> Result<Self, Error> {
+    let (stop, stopped) = mpsc::channel::<()>();
+    thread::Builder::new().name("storedb-expiry".into()).spawn(move || {
+      // Dropping the sweeper disconnects the channel, which ends the wait early.
+      while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+        let Some(pool) = pool.upgrade() else {
+          break;
+        };
+        // Failures, such as a busy database, are left for the next run.
+        let _ = purge(&pool);
+      }
+    })?;
+    Ok(ExpirySweeper { _stop: stop })
+  }
+}
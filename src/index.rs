@@ -0,0 +1,172 @@
+//! Secondary indexes on values.
+//!
+//! `kv_index` maps the index key each index derives from a value to the key of
+//! its entry. Index keys are encoded like entry keys, so they sort the same
+//! way. As they hold parts of values, they are encrypted on any encrypted
+//! database, whether or not entry keys are. `index_meta` records which
+//! indexes exist; the functions deriving their keys only live in memory, so
+//! each process registers them again with `CollectionBuilder::index` or
+//! `Collection::create_index`. A writable collection cannot be opened while
+//! one of its indexes is not registered.
+//!
+//! Every change to `index_meta` increments `index_generation` in
+//! `database_meta`, which tells other handles on the database to read their
+//! index definitions again, see `Pool::indexes`.
+//!
+//! A unique index allows each index key for one live entry only. Writes check
+//! this before changing anything, see `CollectionTx::check_unique`.
+
+use rusqlite::{Connection, OptionalExtension};
+use std::any::type_name;
+use std::sync::Arc;
+use crate::Error;
+use crate::compression::Framing;
+use crate::encryption::{self, Cipher};
+use crate::expiry;
+use crate::schema;
+
+/// Derives an encoded index key from a serialized value, without framing.
+pub(crate) type Extract = dyn Fn(&[u8]) -> Result<Vec<u8>, Error> + Send + Sync;
+
+/// An index of a collection.
+#[derive(Clone)]
+pub(crate) struct Index {
+  pub(crate) name: String,
+  pub(crate) unique: bool,
+  /// Name of the type of the index keys.
+  key_type: String,
+  /// `None` if the index exists but was not created in this process.
+  extract: Option<Arc<Extract>>,
+}
+
+impl Index {
+  pub(crate) fn new<T>(name: &str, unique: bool, extract: Arc<Extract>) -> Self {
+    Index { name: name.to_string(), unique, key_type: type_name::<T>().to_string(), extract: Some(extract) }
+  }
+
+  /// This index with the function of `registered`, if that is the same index.
+  pub(crate) fn registered_from(self, registered: &[Index]) -> Self {
+    let extract = registered.iter()
+      .find(|i| i.name == self.name && i.key_type == self.key_type)
+      .and_then(|i| i.extract.clone());
+    Index { extract, ..self }
+  }
+
+  fn registered(&self) -> bool {
+    self.extract.is_some()
+  }
+
+  /// The encoded index key of a serialized value.
+  pub(crate) fn key(&self, raw: &[u8]) -> Result<Vec<u8>, Error> {
+    match &self.extract {
+      Some(extract) => extract(raw),
+      None => Err(Error::IndexNotRegistered(self.name.clone())),
+    }
+  }
+}
+
+/// Reads the indexes of a collection from `index_meta`, none of them registered yet.
+pub(crate) fn load(conn: &Connection, collection: &str) -> Result<Vec<Index>, Error> {
+  let mut stmt = conn.prepare_cached("SELECT name, is_unique, key_type FROM index_meta WHERE collection = ? ORDER BY name")?;
+  let indexes = stmt.query_map([collection], |row| {
+    Ok(Index { name: row.get(0)?, unique: row.get(1)?, key_type: row.get(2)?, extract: None })
+  })?;
+  Ok(indexes.collect::<Result<_, _>>()?)
+}
+
+/// Fails with `Error::IndexNotRegistered` unless every index has its function.
+pub(crate) fn ensure_registered(indexes: &[Index]) -> Result<(), Error> {
+  match indexes.iter().find(|index| !index.registered()) {
+    Some(index) => Err(Error::IndexNotRegistered(index.name.clone())),
+    None => Ok(()),
+  }
+}
+
+/// The number of changes made to `index_meta` so far.
+pub(crate) fn generation(conn: &Connection) -> Result<i64, Error> {
+  let mut stmt = conn.prepare_cached("SELECT value FROM database_meta WHERE name = 'index_generation'")?;
+  Ok(stmt.query_row([], |row| row.get(0)).optional()?.unwrap_or(0))
+}
+
+/// Records a change to `index_meta`; see `generation`.
+pub(crate) fn changed(conn: &Connection) -> Result<(), Error> {
+  conn.prepare_cached(
+    "INSERT INTO database_meta (name, value) VALUES ('index_generation', 1)
+     ON CONFLICT (name) DO UPDATE SET value = value + 1",
+  )?.execute([])?;
+  Ok(())
+}
+
+pub(crate) fn exists(conn: &Connection, collection: &str, name: &str) -> Result<bool, Error> {
+  let mut stmt = conn.prepare_cached("SELECT 1 FROM index_meta WHERE collection = ? AND name = ?")?;
+  Ok(stmt.exists([collection, name])?)
+}
+
+/// Records an index keyed by `T`. Returns whether it needs to be built, which is
+/// when it is new, was keyed by another type or has become unique.
+pub(crate) fn define<T: serde::de::DeserializeOwned>(conn: &Connection, collection: &str, index: &Index) -> Result<bool, Error> {
+  let key_type = &index.key_type;
+  let key_schema = schema::fingerprint::<T>();
+  let stored: Option<(String, Option<String>, bool)> = conn.query_row(
+    "SELECT key_type, key_schema, is_unique FROM index_meta WHERE collection = ? AND name = ?",
+    [collection, &index.name],
+    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+  ).optional()?;
+  let same_type = match (&stored, &key_schema) {
+    (Some((_, Some(stored), _)), Some(expected)) => stored == expected,
+    (Some((stored, _, _)), _) => stored == key_type,
+    (None, _) => false,
+  };
+  let unique = stored.as_ref().is_some_and(|(_, _, unique)| *unique);
+  let renamed = stored.as_ref().is_some_and(|(stored, _, _)| stored != key_type);
+  if !same_type || renamed || unique != index.unique {
+    conn.execute(
+      "INSERT OR REPLACE INTO index_meta (collection, name, key_type, key_schema, is_unique) VALUES (?, ?, ?, ?, ?)",
+      rusqlite::params![collection, &index.name, key_type, key_schema, index.unique],
+    )?;
+    changed(conn)?;
+  }
+  Ok(!same_type || (index.unique && !unique))
+}
+
+/// Fills an index from the live entries of its collection, replacing what it
+/// held. Fails if the index is unique and two entries have the same index key.
+pub(crate) fn build(
+  conn: &Connection,
+  cipher: Option<&Cipher>,
+  collection: &str,
+  index: &Index,
+  framing: &Framing,
+) -> Result<(), Error> {
+  conn.execute("DELETE FROM kv_index WHERE collection = ? AND name = ?", [collection, &index.name])?;
+  let mut select = conn.prepare(
+    "SELECT key, value FROM kv_store WHERE collection = ? AND (expires_at IS NULL OR expires_at > ?)",
+  )?;
+  let mut insert = conn.prepare_cached("INSERT INTO kv_index (collection, name, index_key, key) VALUES (?, ?, ?, ?)")?;
+  let mut rows = select.query(rusqlite::params![collection, expiry::now()])?;
+  while let Some(row) = rows.next()? {
+    let stored_key: Vec<u8> = row.get(0)?;
+    let key = encryption::open_key(cipher, stored_key.clone())?;
+    let stored = encryption::open_value(cipher, &key, row.get(1)?)?;
+    let index_key = encryption::seal_index_key(cipher, index.key(&framing.unpack(&stored)?)?)?;
+    insert.execute(rusqlite::params![collection, &index.name, index_key, stored_key])?;
+  }
+  if index.unique {
+    let duplicate: Option<Vec<u8>> = conn.query_row(
+      "SELECT MAX(key) FROM kv_index WHERE collection = ? AND name = ? GROUP BY index_key HAVING COUNT(*) > 1",
+      [collection, &index.name],
+      |row| row.get(0),
+    ).optional()?;
+    if let Some(key) = duplicate {
+      return Err(Error::UniqueViolation { index: index.name.clone(), key: encryption::open_key(cipher, key)? });
+    }
+  }
+  Ok(())
+}
+
+/// Deletes an index and its entries.
+pub(crate) fn remove(conn: &Connection, collection: &str, name: &str) -> Result<(), Error> {
+  conn.execute("DELETE FROM kv_index WHERE collection = ? AND name = ?", [collection, name])?;
+  conn.execute("DELETE FROM index_meta WHERE collection = ? AND name = ?", [collection, name])?;
+  changed(conn)
+}
@@ -0,0 +1,191 @@
+use rusqlite::Connection;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::ops::Bound;
+use serde::de::DeserializeOwned;
+use crate::Error;
+use crate::codec::{StoredBytes, ValueCodec};
+use crate::encryption::{self, Cipher};
+use crate::expiry;
+use crate::key_codec;
+
+/// Rows fetched per query while iterating.
+const BATCH_SIZE: usize = 512;
+
+/// Walks the rows of a collection in key order, fetching them in fixed-size
+/// batches so that memory use does not grow with the size of the collection.
+/// Keys and values are decrypted as they are pulled.
+pub(crate) struct RawIter<'a> {
+  conn: &'a Connection,
+  collection: &'a str,
+  cipher: Option<&'a Cipher>,
+  /// Entries that expire by this time are skipped.
+  now: i64,
+  lower: Bound<Vec<u8>>,
+  upper: Bound<Vec<u8>>,
+  reverse: bool,
+  with_values: bool,
+  batch_size: usize,
+  buf: VecDeque<(Vec<u8>, StoredBytes)>,
+  done: bool,
+}
+
+impl<'a> RawIter<'a> {
+  pub(crate) fn new(
+    conn: &'a Connection,
+    collection: &'a str,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    reverse: bool,
+    with_values: bool,
+    cipher: Option<&'a Cipher>,
+  ) -> Self {
+    RawIter {
+      conn,
+      collection,
+      cipher,
+      now: expiry::now(),
+      lower,
+      upper,
+      reverse,
+      with_values,
+      batch_size: BATCH_SIZE,
+      buf: VecDeque::new(),
+      done: false,
+    }
+  }
+
+  /// Caps the number of rows fetched per query, for callers that know they
+  /// only need a few.
+  pub(crate) fn with_batch_size(mut self, batch_size: usize) -> Self {
+    self.batch_size = batch_size.clamp(1, BATCH_SIZE);
+    self
+  }
+
+  fn fetch(&mut self) -> Result<(), Error> {
+    let mut sql = String::from(if self.with_values {
+      "SELECT key, value FROM kv_store WHERE collection = ?"
+    } else {
+      "SELECT key, NULL FROM kv_store WHERE collection = ?"
+    });
+    sql.push_str(" AND (expires_at IS NULL OR expires_at > ?)");
+    let mut params: Vec<&dyn rusqlite::ToSql> = vec![&self.collection, &self.now];
+    match &self.lower {
+      Bound::Included(k) => { sql.push_str(" AND key >= ?"); params.push(k); }
+      Bound::Excluded(k) => { sql.push_str(" AND key > ?"); params.push(k); }
+      Bound::Unbounded => {}
+    }
+    match &self.upper {
+      Bound::Included(k) => { sql.push_str(" AND key <= ?"); params.push(k); }
+      Bound::Excluded(k) => { sql.push_str(" AND key < ?"); params.push(k); }
+      Bound::Unbounded => {}
+    }
+    sql.push_str(if self.reverse { " ORDER BY key DESC" } else { " ORDER BY key" });
+    sql.push_str(" LIMIT ?");
+    params.push(&self.batch_size);
+
+    let mut stmt = self.conn.prepare_cached(&sql)?;
+    let mut rows = stmt.query(params.as_slice())?;
+    while let Some(row) = rows.next()? {
+      let key_bytes: Vec<u8> = row.get(0)?;
+      let value_bytes: Option<StoredBytes> = row.get(1)?;
+      self.buf.push_back((key_bytes, value_bytes.unwrap_or_default()));
+    }
+    drop(rows);
+
+    if self.buf.len() < self.batch_size {
+      self.done = true;
+    }
+    if let Some((last, _)) = self.buf.back() {
+      if self.reverse {
+        self.upper = Bound::Excluded(last.clone());
+      } else {
+        self.lower = Bound::Excluded(last.clone());
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Iterator for RawIter<'_> {
+  type Item = Result<(Vec<u8>, StoredBytes), Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.buf.is_empty() && !self.done {
+      if let Err(e) = self.fetch() {
+        self.done = true;
+        return Some(Err(e));
+      }
+    }
+    let (stored_key, value) = self.buf.pop_front()?;
+    Some(encryption::open_key(self.cipher, stored_key).and_then(|key| {
+      let value = if self.with_values { encryption::open_value(self.cipher, &key, value)? } else { value };
+      Ok((key, value))
+    }))
+  }
+}
+
+/// Iterator over the entries of a collection, decoded as they are pulled.
+pub struct Iter<'a, K, V> {
+  raw: RawIter<'a>,
+  codec: ValueCodec<V>,
+  _phantom: PhantomData<K>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+  pub(crate) fn new(raw: RawIter<'a>, codec: ValueCodec<V>) -> Self {
+    Iter { raw, codec, _phantom: PhantomData }
+  }
+}
+
+impl<K: DeserializeOwned, V: DeserializeOwned> Iterator for Iter<'_, K, V> {
+  type Item = Result<(K, V), Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let (key_bytes, value_bytes) = match self.raw.next()? {
+      Ok(row) => row,
+      Err(e) => return Some(Err(e)),
+    };
+    Some(key_codec::decode_key(&key_bytes).and_then(|key| Ok((key, self.codec.decode(&value_bytes)?))))
+  }
+}
+
+/// Iterator over the keys of a collection, decoded as they are pulled.
+pub struct Keys<'a, K> {
+  raw: RawIter<'a>,
+  _phantom: PhantomData<K>,
+}
+
+impl<'a, K> Keys<'a, K> {
+  pub(crate) fn new(raw: RawIter<'a>) -> Self {
+    Keys { raw, _phantom: PhantomData }
+  }
+}
+
+impl<K: DeserializeOwned> Iterator for Keys<'_, K> {
+  type Item = Result<K, Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    Some(self.raw.next()?.and_then(|(key_bytes, _)| key_codec::decode_key(&key_bytes)))
+  }
+}
+
+/// Iterator over the values of a collection in key order, decoded as they are pulled.
+pub struct Values<'a, V> {
+  raw: RawIter<'a>,
+  codec: ValueCodec<V>,
+}
+
+impl<'a, V> Values<'a, V> {
+  pub(crate) fn new(raw: RawIter<'a>, codec: ValueCodec<V>) -> Self {
+    Values { raw, codec }
+  }
+}
+
+impl<V: DeserializeOwned> Iterator for Values<'_, V> {
+  type Item = Result<V, Error>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    Some(self.raw.next()?.and_then(|(_, value_bytes)| self.codec.decode(&value_bytes)))
+  }
+}
@@ -0,0 +1,608 @@
+//! Order-preserving binary encoding for collection keys.
+//!
+//! Keys are written so that comparing the encoded bytes with `memcmp` (which is
+//! how SQLite orders BLOBs) gives the same result as comparing the original
+//! values with `Ord`. Integers are stored big-endian with the sign bit flipped,
+//! strings and byte slices are escaped and terminated, and sequences mark each
+//! element so that shorter sequences sort first.
+
+use crate::Error;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_NUL: u8 = 0xFF;
+const TERMINATOR: u8 = 0x00;
+const SEQ_ELEMENT: u8 = 0x01;
+const SEQ_END: u8 = 0x00;
+
+pub(crate) fn encode_key<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+  let mut ser = KeySerializer { out: Vec::new(), prefix: false, depth: 0 };
+  value.serialize(&mut ser)?;
+  Ok(ser.out)
+}
+
+/// Encodes a partial key. The outermost variable-length value (a string, byte
+/// slice or sequence) is left open so that every key continuing it matches.
+pub(crate) fn encode_prefix<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+  let mut ser = KeySerializer { out: Vec::new(), prefix: true, depth: 0 };
+  value.serialize(&mut ser)?;
+  Ok(ser.out)
+}
+
+pub(crate) fn decode_key<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+  let mut de = KeyDeserializer { input: bytes };
+  let value = T::deserialize(&mut de)?;
+  if !de.input.is_empty() {
+    return Err(Error::KeyEncodingError("trailing bytes after key".into()));
+  }
+  Ok(value)
+}
+
+/// Returns the smallest byte string greater than every string starting with
+/// `prefix`, or `None` if no such string exists.
+pub(crate) fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+  let mut end = prefix.to_vec();
+  while let Some(last) = end.pop() {
+    if last < 0xFF {
+      end.push(last + 1);
+      return Some(end);
+    }
+  }
+  None
+}
+
+struct KeySerializer {
+  out: Vec<u8>,
+  prefix: bool,
+  depth: usize,
+}
+
+impl KeySerializer {
+  fn is_open(&self) -> bool {
+    self.prefix && self.depth == 0
+  }
+
+  fn write_escaped(&mut self, bytes: &[u8]) {
+    for &b in bytes {
+      if b == ESCAPE {
+        self.out.extend_from_slice(&[ESCAPE, ESCAPED_NUL]);
+      } else {
+        self.out.push(b);
+      }
+    }
+    if !self.is_open() {
+      self.out.extend_from_slice(&[ESCAPE, TERMINATOR]);
+    }
+  }
+
+  fn write_variant(&mut self, index: u32) {
+    self.out.extend_from_slice(&index.to_be_bytes());
+  }
+}
+
+impl ser::Serializer for &mut KeySerializer {
+  type Ok = ();
+  type Error = Error;
+  type SerializeSeq = SeqSerializer<Self>;
+  type SerializeTuple = Self;
+  type SerializeTupleStruct = Self;
+  type SerializeTupleVariant = Self;
+  type SerializeMap = SeqSerializer<Self>;
+  type SerializeStruct = Self;
+  type SerializeStructVariant = Self;
+
+  fn serialize_bool(self, v: bool) -> Result<(), Error> {
+    self.out.push(v as u8);
+    Ok(())
+  }
+
+  fn serialize_i8(self, v: i8) -> Result<(), Error> {
+    self.out.push((v as u8) ^ 0x80);
+    Ok(())
+  }
+
+  fn serialize_i16(self, v: i16) -> Result<(), Error> {
+    self.out.extend_from_slice(&((v as u16) ^ (1 << 15)).to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_i32(self, v: i32) -> Result<(), Error> {
+    self.out.extend_from_slice(&((v as u32) ^ (1 << 31)).to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_i64(self, v: i64) -> Result<(), Error> {
+    self.out.extend_from_slice(&((v as u64) ^ (1 << 63)).to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_i128(self, v: i128) -> Result<(), Error> {
+    self.out.extend_from_slice(&((v as u128) ^ (1 << 127)).to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_u8(self, v: u8) -> Result<(), Error> {
+    self.out.push(v);
+    Ok(())
+  }
+
+  fn serialize_u16(self, v: u16) -> Result<(), Error> {
+    self.out.extend_from_slice(&v.to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_u32(self, v: u32) -> Result<(), Error> {
+    self.out.extend_from_slice(&v.to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_u64(self, v: u64) -> Result<(), Error> {
+    self.out.extend_from_slice(&v.to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_u128(self, v: u128) -> Result<(), Error> {
+    self.out.extend_from_slice(&v.to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_f32(self, v: f32) -> Result<(), Error> {
+    let bits = v.to_bits();
+    let bits = if bits >> 31 == 1 { !bits } else { bits ^ (1 << 31) };
+    self.out.extend_from_slice(&bits.to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_f64(self, v: f64) -> Result<(), Error> {
+    let bits = v.to_bits();
+    let bits = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+    self.out.extend_from_slice(&bits.to_be_bytes());
+    Ok(())
+  }
+
+  fn serialize_char(self, v: char) -> Result<(), Error> {
+    self.serialize_u32(v as u32)
+  }
+
+  fn serialize_str(self, v: &str) -> Result<(), Error> {
+    self.write_escaped(v.as_bytes());
+    Ok(())
+  }
+
+  fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+    self.write_escaped(v);
+    Ok(())
+  }
+
+  fn serialize_none(self) -> Result<(), Error> {
+    self.out.push(0);
+    Ok(())
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+    self.out.push(1);
+    value.serialize(self)
+  }
+
+  fn serialize_unit(self) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn serialize_unit_variant(self, _name: &'static str, index: u32, _variant: &'static str) -> Result<(), Error> {
+    self.write_variant(index);
+    Ok(())
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<(), Error> {
+    value.serialize(self)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    index: u32,
+    _variant: &'static str,
+    value: &T,
+  ) -> Result<(), Error> {
+    self.write_variant(index);
+    self.depth += 1;
+    value.serialize(&mut *self)?;
+    self.depth -= 1;
+    Ok(())
+  }
+
+  fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+    let open = self.is_open();
+    self.depth += 1;
+    Ok(SeqSerializer { ser: self, open })
+  }
+
+  fn serialize_tuple(self, _len: usize) -> Result<Self, Error> {
+    self.depth += 1;
+    Ok(self)
+  }
+
+  fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self, Error> {
+    self.depth += 1;
+    Ok(self)
+  }
+
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self, Error> {
+    self.write_variant(index);
+    self.depth += 1;
+    Ok(self)
+  }
+
+  fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+    let open = self.is_open();
+    self.depth += 1;
+    Ok(SeqSerializer { ser: self, open })
+  }
+
+  fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, Error> {
+    self.depth += 1;
+    Ok(self)
+  }
+
+  fn serialize_struct_variant(
+    self,
+    _name: &'static str,
+    index: u32,
+    _variant: &'static str,
+    _len: usize,
+  ) -> Result<Self, Error> {
+    self.write_variant(index);
+    self.depth += 1;
+    Ok(self)
+  }
+
+  fn is_human_readable(&self) -> bool {
+    false
+  }
+}
+
+struct SeqSerializer<S> {
+  ser: S,
+  open: bool,
+}
+
+impl ser::SerializeSeq for SeqSerializer<&mut KeySerializer> {
+  type Ok = ();
+  type Error = Error;
+
+  fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+    self.ser.out.push(SEQ_ELEMENT);
+    value.serialize(&mut *self.ser)
+  }
+
+  fn end(self) -> Result<(), Error> {
+    self.ser.depth -= 1;
+    if !self.open {
+      self.ser.out.push(SEQ_END);
+    }
+    Ok(())
+  }
+}
+
+impl ser::SerializeMap for SeqSerializer<&mut KeySerializer> {
+  type Ok = ();
+  type Error = Error;
+
+  fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+    self.ser.out.push(SEQ_ELEMENT);
+    key.serialize(&mut *self.ser)
+  }
+
+  fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+    value.serialize(&mut *self.ser)
+  }
+
+  fn end(self) -> Result<(), Error> {
+    ser::SerializeSeq::end(self)
+  }
+}
+
+macro_rules! impl_compound {
+  ($trait:ident, $method:ident $(, $key:ident)?) => {
+    impl ser::$trait for &mut KeySerializer {
+      type Ok = ();
+      type Error = Error;
+
+      fn $method<T: Serialize + ?Sized>(&mut self, $($key: &'static str,)? value: &T) -> Result<(), Error> {
+        value.serialize(&mut **self)
+      }
+
+      fn end(self) -> Result<(), Error> {
+        self.depth -= 1;
+        Ok(())
+      }
+    }
+  };
+}
+
+impl_compound!(SerializeTuple, serialize_element);
+impl_compound!(SerializeTupleStruct, serialize_field);
+impl_compound!(SerializeTupleVariant, serialize_field);
+impl_compound!(SerializeStruct, serialize_field, _key);
+impl_compound!(SerializeStructVariant, serialize_field, _key);
+
+struct KeyDeserializer<'de> {
+  input: &'de [u8],
+}
+
+impl<'de> KeyDeserializer<'de> {
+  fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+    if self.input.len() < N {
+      return Err(Error::KeyEncodingError("unexpected end of key".into()));
+    }
+    let (head, rest) = self.input.split_at(N);
+    self.input = rest;
+    Ok(head.try_into().unwrap())
+  }
+
+  fn take_byte(&mut self) -> Result<u8, Error> {
+    Ok(self.take::<1>()?[0])
+  }
+
+  fn take_escaped(&mut self) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::new();
+    loop {
+      match self.take_byte()? {
+        ESCAPE => match self.take_byte()? {
+          TERMINATOR => return Ok(buf),
+          ESCAPED_NUL => buf.push(0),
+          b => return Err(Error::KeyEncodingError(format!("invalid escape byte {:#04x}", b))),
+        },
+        b => buf.push(b),
+      }
+    }
+  }
+}
+
+macro_rules! deserialize_int {
+  ($method:ident, $visit:ident, $ty:ty, $uty:ty, $flip:expr) => {
+    fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+      let raw = <$uty>::from_be_bytes(self.take()?);
+      visitor.$visit((raw ^ $flip) as $ty)
+    }
+  };
+}
+
+impl<'de> de::Deserializer<'de> for &mut KeyDeserializer<'de> {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+    Err(Error::KeyEncodingError("key encoding is not self-describing".into()))
+  }
+
+  fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    match self.take_byte()? {
+      0 => visitor.visit_bool(false),
+      1 => visitor.visit_bool(true),
+      b => Err(Error::KeyEncodingError(format!("invalid bool byte {:#04x}", b))),
+    }
+  }
+
+  deserialize_int!(deserialize_i8, visit_i8, i8, u8, 1 << 7);
+  deserialize_int!(deserialize_i16, visit_i16, i16, u16, 1 << 15);
+  deserialize_int!(deserialize_i32, visit_i32, i32, u32, 1 << 31);
+  deserialize_int!(deserialize_i64, visit_i64, i64, u64, 1 << 63);
+  deserialize_int!(deserialize_i128, visit_i128, i128, u128, 1 << 127);
+  deserialize_int!(deserialize_u8, visit_u8, u8, u8, 0);
+  deserialize_int!(deserialize_u16, visit_u16, u16, u16, 0);
+  deserialize_int!(deserialize_u32, visit_u32, u32, u32, 0);
+  deserialize_int!(deserialize_u64, visit_u64, u64, u64, 0);
+  deserialize_int!(deserialize_u128, visit_u128, u128, u128, 0);
+
+  fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    let bits = u32::from_be_bytes(self.take()?);
+    let bits = if bits >> 31 == 1 { bits ^ (1 << 31) } else { !bits };
+    visitor.visit_f32(f32::from_bits(bits))
+  }
+
+  fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    let bits = u64::from_be_bytes(self.take()?);
+    let bits = if bits >> 63 == 1 { bits ^ (1 << 63) } else { !bits };
+    visitor.visit_f64(f64::from_bits(bits))
+  }
+
+  fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    let raw = u32::from_be_bytes(self.take()?);
+    let c = char::from_u32(raw).ok_or_else(|| Error::KeyEncodingError(format!("invalid char {:#x}", raw)))?;
+    visitor.visit_char(c)
+  }
+
+  fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    self.deserialize_string(visitor)
+  }
+
+  fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    let bytes = self.take_escaped()?;
+    let s = String::from_utf8(bytes).map_err(|e| Error::KeyEncodingError(e.to_string()))?;
+    visitor.visit_string(s)
+  }
+
+  fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    self.deserialize_byte_buf(visitor)
+  }
+
+  fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_byte_buf(self.take_escaped()?)
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    match self.take_byte()? {
+      0 => visitor.visit_none(),
+      1 => visitor.visit_some(self),
+      b => Err(Error::KeyEncodingError(format!("invalid option tag {:#04x}", b))),
+    }
+  }
+
+  fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_unit()
+  }
+
+  fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_unit()
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_seq(MarkedAccess { de: self })
+  }
+
+  fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_seq(FixedAccess { de: self, remaining: len })
+  }
+
+  fn deserialize_tuple_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    len: usize,
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.deserialize_tuple(len, visitor)
+  }
+
+  fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_map(MarkedAccess { de: self })
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    self.deserialize_tuple(fields.len(), visitor)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, Error> {
+    visitor.visit_enum(self)
+  }
+
+  fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+    Err(Error::KeyEncodingError("identifiers are not stored in keys".into()))
+  }
+
+  fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+    Err(Error::KeyEncodingError("key encoding is not self-describing".into()))
+  }
+
+  fn is_human_readable(&self) -> bool {
+    false
+  }
+}
+
+struct MarkedAccess<'a, 'de> {
+  de: &'a mut KeyDeserializer<'de>,
+}
+
+impl MarkedAccess<'_, '_> {
+  fn has_next(&mut self) -> Result<bool, Error> {
+    match self.de.take_byte()? {
+      SEQ_ELEMENT => Ok(true),
+      SEQ_END => Ok(false),
+      b => Err(Error::KeyEncodingError(format!("invalid sequence marker {:#04x}", b))),
+    }
+  }
+}
+
+impl<'de> de::SeqAccess<'de> for MarkedAccess<'_, 'de> {
+  type Error = Error;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+    if self.has_next()? {
+      seed.deserialize(&mut *self.de).map(Some)
+    } else {
+      Ok(None)
+    }
+  }
+}
+
+impl<'de> de::MapAccess<'de> for MarkedAccess<'_, 'de> {
+  type Error = Error;
+
+  fn next_key_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+    if self.has_next()? {
+      seed.deserialize(&mut *self.de).map(Some)
+    } else {
+      Ok(None)
+    }
+  }
+
+  fn next_value_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, Error> {
+    seed.deserialize(&mut *self.de)
+  }
+}
+
+struct FixedAccess<'a, 'de> {
+  de: &'a mut KeyDeserializer<'de>,
+  remaining: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for FixedAccess<'_, 'de> {
+  type Error = Error;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Error> {
+    if self.remaining == 0 {
+      return Ok(None);
+    }
+    self.remaining -= 1;
+    seed.deserialize(&mut *self.de).map(Some)
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.remaining)
+  }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut KeyDeserializer<'de> {
+  type Error = Error;
+  type Variant = Self;
+
+  fn variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<(T::Value, Self), Error> {
+    let index = u32::from_be_bytes(self.take()?);
+    let value = seed.deserialize(IntoDeserializer::<Error>::into_deserializer(index))?;
+    Ok((value, self))
+  }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut KeyDeserializer<'de> {
+  type Error = Error;
+
+  fn unit_variant(self) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+    seed.deserialize(self)
+  }
+
+  fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+    de::Deserializer::deserialize_tuple(self, len, visitor)
+  }
+
+  fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+    de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+  }
+}
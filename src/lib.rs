@@ -2,7 +2,7 @@
 //!
 //! StoreDB is a disk-backed, transactional key-value database built using `rusqlite` in Rust.
 //! It supports multiple named collections stored in a single underlying table.
-//! Uses `postcard` for serialization.
+//! Values are serialized with `postcard` by default; see `Codec` for the alternatives.
 //!
 //! ## Example Usage
 //! ```rust
@@ -16,8 +16,8 @@
 //! }
 //!
 //! fn main() -> Result<(), Error> {
-//!   let mut db = Database::new("example.db")?;
-//!   let mut users = db.get_collection::<u32, User>("users")?;
+//!   let db = Database::temporary()?;
+//!   let users = db.get_collection::<u32, User>("users")?;
 //!   let mut tx = users.begin()?;
 //!
 //!   tx.put(1u32, User { id: 1, name: "Alice".into() })?;
@@ -32,11 +32,40 @@
 //! ```
 
 mod database;
+mod database_tx;
 mod err;
 mod collection;
 mod collection_tx;
+mod codec;
+mod compression;
+mod encryption;
+mod expiry;
+mod index;
+mod key_codec;
+mod iter;
+mod options;
+mod page;
+mod pool;
+mod retry;
+mod schema;
+mod watch;
+#[cfg(feature = "async")]
+mod async_db;
 
 pub use database::*;
+pub use database_tx::*;
 pub use err::*;
 pub use collection::*;
 pub use collection_tx::*;
+pub use codec::*;
+pub use compression::Compression;
+#[cfg(feature = "encryption")]
+pub use encryption::EncryptionKey;
+pub use expiry::ExpirySweeper;
+pub use iter::{Iter, Keys, Values};
+pub use options::*;
+pub use page::*;
+pub use retry::RetryPolicy;
+pub use watch::Change;
+#[cfg(feature = "async")]
+pub use async_db::*;
@@ -0,0 +1,224 @@
+use rusqlite::{Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::{Database, Error};
+#[cfg(feature = "encryption")]
+use crate::EncryptionKey;
+
+/// Idle read connections kept open for reuse by default.
+const DEFAULT_READER_POOL_SIZE: usize = 8;
+/// Prepared statements cached per connection. Each collection operation and
+/// range shape uses its own, so rusqlite's default of 16 is easily exceeded.
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// SQLite journal mode, see <https://www.sqlite.org/pragma.html#pragma_journal_mode>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+  /// Write-ahead log. Readers do not block the writer and vice versa.
+  Wal,
+  /// Rollback journal, deleted at the end of each transaction.
+  Delete,
+  /// Rollback journal, truncated at the end of each transaction.
+  Truncate,
+  /// Rollback journal, invalidated but kept at the end of each transaction.
+  Persist,
+}
+
+impl JournalMode {
+  fn as_str(self) -> &'static str {
+    match self {
+      JournalMode::Wal => "WAL",
+      JournalMode::Delete => "DELETE",
+      JournalMode::Truncate => "TRUNCATE",
+      JournalMode::Persist => "PERSIST",
+    }
+  }
+}
+
+/// SQLite synchronous level, see <https://www.sqlite.org/pragma.html#pragma_synchronous>.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Synchronous {
+  Off,
+  Normal,
+  Full,
+  Extra,
+}
+
+impl Synchronous {
+  fn as_str(self) -> &'static str {
+    match self {
+      Synchronous::Off => "OFF",
+      Synchronous::Normal => "NORMAL",
+      Synchronous::Full => "FULL",
+      Synchronous::Extra => "EXTRA",
+    }
+  }
+}
+
+/// Settings for opening a `Database`, created with `Database::builder`.
+#[derive(Debug, Clone)]
+pub struct DatabaseOptions {
+  pub(crate) path: PathBuf,
+  journal_mode: JournalMode,
+  synchronous: Option<Synchronous>,
+  busy_timeout: Option<Duration>,
+  cache_size: Option<i64>,
+  page_size: Option<u32>,
+  mmap_size: Option<u64>,
+  pub(crate) read_only: bool,
+  create_if_missing: bool,
+  pub(crate) reader_pool_size: usize,
+  #[cfg(feature = "encryption")]
+  pub(crate) encryption_key: Option<EncryptionKey>,
+  #[cfg(feature = "encryption")]
+  pub(crate) encrypt_keys: bool,
+}
+
+impl DatabaseOptions {
+  pub(crate) fn new<P: AsRef<Path>>(path: P) -> Self {
+    DatabaseOptions {
+      path: path.as_ref().to_path_buf(),
+      journal_mode: JournalMode::Wal,
+      synchronous: None,
+      busy_timeout: None,
+      cache_size: None,
+      page_size: None,
+      mmap_size: None,
+      read_only: false,
+      create_if_missing: true,
+      reader_pool_size: DEFAULT_READER_POOL_SIZE,
+      #[cfg(feature = "encryption")]
+      encryption_key: None,
+      #[cfg(feature = "encryption")]
+      encrypt_keys: false,
+    }
+  }
+
+  /// Journal mode for file-backed databases. Defaults to `JournalMode::Wal`.
+  pub fn journal_mode(mut self, mode: JournalMode) -> Self {
+    self.journal_mode = mode;
+    self
+  }
+
+  /// Synchronous level of the writer connection. SQLite defaults to `Full`.
+  pub fn synchronous(mut self, level: Synchronous) -> Self {
+    self.synchronous = Some(level);
+    self
+  }
+
+  /// How long a connection waits on a locked database before failing with a
+  /// busy error. rusqlite defaults to five seconds.
+  pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+    self.busy_timeout = Some(timeout);
+    self
+  }
+
+  /// Page cache size per connection, as `PRAGMA cache_size` takes it: pages if
+  /// positive, KiB if negative.
+  pub fn cache_size(mut self, size: i64) -> Self {
+    self.cache_size = Some(size);
+    self
+  }
+
+  /// Page size in bytes. Only takes effect when the database file is created.
+  pub fn page_size(mut self, size: u32) -> Self {
+    self.page_size = Some(size);
+    self
+  }
+
+  /// Maximum number of bytes of the database file to memory-map per connection.
+  pub fn mmap_size(mut self, size: u64) -> Self {
+    self.mmap_size = Some(size);
+    self
+  }
+
+  /// Opens the database read-only. Writes and creating new collections fail, as
+  /// does opening a database or collection that still needs to be upgraded:
+  /// see `Error::UpgradeRequired`.
+  pub fn read_only(mut self, read_only: bool) -> Self {
+    self.read_only = read_only;
+    self
+  }
+
+  /// Whether to create the database file if it does not exist. Defaults to `true`.
+  pub fn create_if_missing(mut self, create: bool) -> Self {
+    self.create_if_missing = create;
+    self
+  }
+
+  /// Number of idle read connections kept open for reuse. Defaults to 8.
+  pub fn reader_pool_size(mut self, size: usize) -> Self {
+    self.reader_pool_size = size;
+    self
+  }
+
+  /// Encrypts stored values with `key`. An unencrypted database is encrypted
+  /// when it is first opened with a key; after that, it can only be opened with
+  /// the same key, until `Database::rotate_encryption_key` changes it.
+  #[cfg(feature = "encryption")]
+  pub fn encryption_key(mut self, key: EncryptionKey) -> Self {
+    self.encryption_key = Some(key);
+    self
+  }
+
+  /// Encrypts keys as well as values. Keys are encrypted deterministically,
+  /// so lookups by key still work, but they are no longer stored in order:
+  /// scans return entries in no particular order, and range and prefix
+  /// queries fail with `Error::KeyOrderUnavailable`. Must be chosen when the
+  /// database is first encrypted.
+  #[cfg(feature = "encryption")]
+  pub fn encrypt_keys(mut self, encrypt: bool) -> Self {
+    self.encrypt_keys = encrypt;
+    self
+  }
+
+  pub fn open(self) -> Result<Database, Error> {
+    Database::open(self, None)
+  }
+
+  pub(crate) fn open_writer(&self) -> Result<Connection, Error> {
+    let mut flags = OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    if self.read_only {
+      flags |= OpenFlags::SQLITE_OPEN_READ_ONLY;
+    } else {
+      flags |= OpenFlags::SQLITE_OPEN_READ_WRITE;
+      if self.create_if_missing {
+        flags |= OpenFlags::SQLITE_OPEN_CREATE;
+      }
+    }
+    let conn = Connection::open_with_flags(&self.path, flags)?;
+    self.configure(&conn)?;
+    if let Some(size) = self.page_size {
+      conn.pragma_update(None, "page_size", size)?;
+    }
+    if !self.read_only && conn.path().is_some_and(|p| !p.is_empty()) {
+      conn.pragma_update(None, "journal_mode", self.journal_mode.as_str())?;
+    }
+    if let Some(level) = self.synchronous {
+      conn.pragma_update(None, "synchronous", level.as_str())?;
+    }
+    Ok(conn)
+  }
+
+  pub(crate) fn open_reader(&self, path: &Path) -> Result<Connection, Error> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let conn = Connection::open_with_flags(path, flags)?;
+    self.configure(&conn)?;
+    Ok(conn)
+  }
+
+  /// Applies the settings that SQLite keeps per connection.
+  fn configure(&self, conn: &Connection) -> Result<(), Error> {
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    if let Some(timeout) = self.busy_timeout {
+      conn.busy_timeout(timeout)?;
+    }
+    if let Some(size) = self.cache_size {
+      conn.pragma_update(None, "cache_size", size)?;
+    }
+    if let Some(size) = self.mmap_size {
+      conn.pragma_update(None, "mmap_size", size)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::Error;
+
+/// One page of a collection listing, see `CollectionTx::page`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<K, V> {
+  pub entries: Vec<(K, V)>,
+  /// Resumes the listing after the last entry, or `None` if this was the last page.
+  pub next: Option<PageCursor>,
+}
+
+/// Opaque position in a collection listing.
+///
+/// Cursors stay valid across transactions and can be passed around as strings
+/// through `Display` and `FromStr`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PageCursor(pub(crate) Vec<u8>);
+
+impl fmt::Display for PageCursor {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for b in &self.0 {
+      write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for PageCursor {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Error> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+      return Err(Error::InvalidCursor);
+    }
+    (0..s.len())
+      .step_by(2)
+      .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| Error::InvalidCursor))
+      .collect::<Result<Vec<u8>, Error>>()
+      .map(PageCursor)
+  }
+}
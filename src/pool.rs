@@ -0,0 +1,317 @@
+use rusqlite::Connection;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread::{self, ThreadId};
+use tempfile::TempDir;
+use crate::Error;
+use crate::encryption::{self, Cipher};
+use crate::index::{self, Index};
+use crate::options::DatabaseOptions;
+use crate::watch::{RawChange, Subscribers};
+
+/// The connections behind a `Database`: a single writer, which serializes all
+/// writes, and a set of read-only connections so readers can run in parallel.
+pub(crate) struct Pool {
+  writer: Mutex<Connection>,
+  writer_owner: Mutex<Option<ThreadId>>,
+  readers: Mutex<Vec<Connection>>,
+  /// `None` for in-memory databases, which cannot be opened a second time.
+  path: Option<PathBuf>,
+  options: DatabaseOptions,
+  /// Ciphers of an encrypted database. Held for reading while a transaction
+  /// takes its snapshot, so that a key rotation cannot commit in between.
+  cipher: RwLock<Option<Arc<Cipher>>>,
+  /// Indexes of each collection, read from `index_meta` when first needed.
+  indexes: RwLock<IndexRegistry>,
+  pub(crate) subscribers: Subscribers,
+  /// Directory of a `Database::temporary` database. Declared last so it is
+  /// removed after the connections above are closed.
+  _temp_dir: Option<TempDir>,
+}
+
+impl Pool {
+  pub(crate) fn new(
+    writer: Connection,
+    path: Option<PathBuf>,
+    options: DatabaseOptions,
+    cipher: Option<Cipher>,
+    temp_dir: Option<TempDir>,
+  ) -> Self {
+    Pool {
+      writer: Mutex::new(writer),
+      writer_owner: Mutex::new(None),
+      readers: Mutex::new(Vec::new()),
+      path,
+      options,
+      cipher: RwLock::new(cipher.map(Arc::new)),
+      indexes: RwLock::default(),
+      subscribers: Subscribers::default(),
+      _temp_dir: temp_dir,
+    }
+  }
+
+  /// The current ciphers. Transactions should use `Txn::cipher`, which stays
+  /// consistent with their snapshot.
+  pub(crate) fn cipher(&self) -> Option<Arc<Cipher>> {
+    self.cipher.read().unwrap_or_else(|e| e.into_inner()).clone()
+  }
+
+  /// Commits a transaction that re-encrypted the database, switching to `cipher`
+  /// once no transaction can see the old data any more.
+  #[cfg(feature = "encryption")]
+  pub(crate) fn commit_with_cipher(&self, tx: Txn<'_>, cipher: Cipher) -> Result<(), Error> {
+    let mut current = self.cipher.write().unwrap_or_else(|e| e.into_inner());
+    tx.commit()?;
+    *current = Some(Arc::new(cipher));
+    Ok(())
+  }
+
+  /// The indexes of a collection, loading them through `conn` if needed.
+  pub(crate) fn indexes(&self, conn: &Connection, collection: &str) -> Result<Arc<[Index]>, Error> {
+    if let Some(indexes) = self.indexes.read().unwrap_or_else(|e| e.into_inner()).collections.get(collection) {
+      return Ok(indexes.clone());
+    }
+    let loaded: Arc<[Index]> = index::load(conn, collection)?.into();
+    let mut registry = self.indexes.write().unwrap_or_else(|e| e.into_inner());
+    Ok(registry.collections.entry(collection.to_string()).or_insert(loaded).clone())
+  }
+
+  /// Reads the loaded indexes again if another handle changed `index_meta`
+  /// since, keeping the functions registered for indexes that still exist.
+  pub(crate) fn refresh_indexes(&self, conn: &Connection) -> Result<(), Error> {
+    let generation = index::generation(conn)?;
+    if self.indexes.read().unwrap_or_else(|e| e.into_inner()).generation == generation {
+      return Ok(());
+    }
+    let mut registry = self.indexes.write().unwrap_or_else(|e| e.into_inner());
+    let mut collections = HashMap::new();
+    for (collection, registered) in &registry.collections {
+      let indexes = index::load(conn, collection)?.into_iter().map(|i| i.registered_from(registered)).collect();
+      collections.insert(collection.clone(), indexes);
+    }
+    *registry = IndexRegistry { generation, collections };
+    Ok(())
+  }
+
+  /// Commits a transaction that defined `index`, adding it to the indexes of a
+  /// collection whose indexes were loaded. The registry stays locked until
+  /// then, so that no other transaction loads the index without its function.
+  pub(crate) fn commit_with_index(&self, tx: Txn<'_>, collection: &str, index: Index) -> Result<(), Error> {
+    let mut registry = self.indexes.write().unwrap_or_else(|e| e.into_inner());
+    tx.commit()?;
+    registry.edit(collection, |indexes| {
+      indexes.retain(|i| i.name != index.name);
+      indexes.push(index);
+    });
+    Ok(())
+  }
+
+  pub(crate) fn unregister_index(&self, collection: &str, name: &str) {
+    let mut registry = self.indexes.write().unwrap_or_else(|e| e.into_inner());
+    registry.edit(collection, |indexes| indexes.retain(|i| i.name != name));
+  }
+
+  /// Gives collection `to` the indexes of `from`, after it was copied.
+  pub(crate) fn copy_indexes(&self, from: &str, to: &str) {
+    let mut registry = self.indexes.write().unwrap_or_else(|e| e.into_inner());
+    if let Some(copied) = registry.collections.get(from).cloned() {
+      registry.collections.insert(to.to_string(), copied);
+    }
+  }
+
+  /// Forgets the indexes of a collection that was dropped, renamed or migrated.
+  pub(crate) fn forget_indexes(&self, collection: &str) {
+    self.indexes.write().unwrap_or_else(|e| e.into_inner()).collections.remove(collection);
+  }
+
+  pub(crate) fn path(&self) -> Option<&Path> {
+    self.path.as_deref()
+  }
+
+  /// Waits for the writer connection. Fails instead of deadlocking if the
+  /// calling thread already holds it.
+  pub(crate) fn writer(&self) -> Result<PooledConn<'_>, Error> {
+    let me = thread::current().id();
+    if *lock(&self.writer_owner) == Some(me) {
+      return Err(Error::NestedTransaction);
+    }
+    let guard = lock(&self.writer);
+    *lock(&self.writer_owner) = Some(me);
+    Ok(PooledConn::Writer(WriterGuard { conn: guard, owner: &self.writer_owner }))
+  }
+
+  /// Takes an idle read connection or opens a new one.
+  pub(crate) fn reader(&self) -> Result<PooledConn<'_>, Error> {
+    let Some(path) = &self.path else {
+      return self.writer();
+    };
+    let idle = lock(&self.readers).pop();
+    let conn = match idle {
+      Some(conn) => conn,
+      None => self.options.open_reader(path)?,
+    };
+    Ok(PooledConn::Reader(ReaderGuard { conn: Some(conn), pool: self }))
+  }
+}
+
+/// The indexes loaded by a pool.
+#[derive(Default)]
+struct IndexRegistry {
+  /// `index_generation` of the database when the indexes were read.
+  generation: i64,
+  collections: HashMap<String, Arc<[Index]>>,
+}
+
+impl IndexRegistry {
+  fn edit(&mut self, collection: &str, f: impl FnOnce(&mut Vec<Index>)) {
+    let mut edited = self.collections.get(collection).map(|i| i.to_vec()).unwrap_or_default();
+    f(&mut edited);
+    self.collections.insert(collection.to_string(), edited.into());
+  }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(crate) struct WriterGuard<'a> {
+  conn: MutexGuard<'a, Connection>,
+  owner: &'a Mutex<Option<ThreadId>>,
+}
+
+impl Drop for WriterGuard<'_> {
+  fn drop(&mut self) {
+    *lock(self.owner) = None;
+  }
+}
+
+pub(crate) struct ReaderGuard<'a> {
+  conn: Option<Connection>,
+  pool: &'a Pool,
+}
+
+impl Drop for ReaderGuard<'_> {
+  fn drop(&mut self) {
+    if let Some(conn) = self.conn.take() {
+      let mut idle = lock(&self.pool.readers);
+      if idle.len() < self.pool.options.reader_pool_size {
+        idle.push(conn);
+      }
+    }
+  }
+}
+
+/// A connection checked out of the pool, returned when dropped.
+pub(crate) enum PooledConn<'a> {
+  Writer(WriterGuard<'a>),
+  Reader(ReaderGuard<'a>),
+}
+
+impl Deref for PooledConn<'_> {
+  type Target = Connection;
+
+  fn deref(&self) -> &Connection {
+    match self {
+      PooledConn::Writer(guard) => &guard.conn,
+      PooledConn::Reader(guard) => guard.conn.as_ref().unwrap(),
+    }
+  }
+}
+
+/// An open SQLite transaction on a pooled connection. Rolled back on drop
+/// unless committed.
+pub(crate) struct Txn<'a> {
+  pool: &'a Pool,
+  conn: PooledConn<'a>,
+  cipher: Option<Arc<Cipher>>,
+  /// Changes to collections with subscribers, sent to them on commit.
+  changes: RefCell<Vec<(String, RawChange)>>,
+  /// Whether the indexes were checked against this transaction's snapshot.
+  indexes_refreshed: Cell<bool>,
+  open: bool,
+}
+
+impl<'a> Txn<'a> {
+  /// Takes the writer and starts an immediate transaction on it, checking that
+  /// no other handle changed the encryption of the database.
+  pub(crate) fn write(pool: &'a Pool) -> Result<Self, Error> {
+    let conn = pool.writer()?;
+    run(&conn, "BEGIN IMMEDIATE")?;
+    let tx = Txn { pool, conn, cipher: pool.cipher(), changes: RefCell::default(), indexes_refreshed: Cell::new(false), open: true };
+    encryption::verify(&tx, tx.cipher())?;
+    Ok(tx)
+  }
+
+  /// Starts a deferred transaction on a read connection. SQLite takes the read
+  /// snapshot on the first query, which checks the encryption like `write` and
+  /// is run here, with the cipher locked.
+  pub(crate) fn read(pool: &'a Pool) -> Result<Self, Error> {
+    let conn = pool.reader()?;
+    let cipher = pool.cipher.read().unwrap_or_else(|e| e.into_inner());
+    run(&conn, "BEGIN DEFERRED")?;
+    let tx = Txn { pool, conn, cipher: cipher.clone(), changes: RefCell::default(), indexes_refreshed: Cell::new(false), open: true };
+    encryption::verify(&tx, tx.cipher())?;
+    Ok(tx)
+  }
+
+  pub(crate) fn cipher(&self) -> Option<&Cipher> {
+    self.cipher.as_deref()
+  }
+
+  /// The indexes of a collection, see `Pool::indexes`. The first call
+  /// reloads them if they changed.
+  pub(crate) fn indexes(&self, collection: &str) -> Result<Arc<[Index]>, Error> {
+    if !self.indexes_refreshed.replace(true) {
+      self.pool.refresh_indexes(self)?;
+    }
+    self.pool.indexes(self, collection)
+  }
+
+  /// Whether changes to a collection should be recorded.
+  pub(crate) fn watched(&self, collection: &str) -> bool {
+    self.pool.subscribers.watched(collection)
+  }
+
+  pub(crate) fn record(&self, collection: &str, change: RawChange) {
+    self.changes.borrow_mut().push((collection.to_string(), change));
+  }
+
+  pub(crate) fn commit(mut self) -> Result<(), Error> {
+    run(&self.conn, "COMMIT")?;
+    self.open = false;
+    self.pool.subscribers.notify(self.changes.take());
+    Ok(())
+  }
+
+  pub(crate) fn rollback(mut self) -> Result<(), Error> {
+    self.open = false;
+    run(&self.conn, "ROLLBACK")?;
+    Ok(())
+  }
+}
+
+impl Deref for Txn<'_> {
+  type Target = Connection;
+
+  fn deref(&self) -> &Connection {
+    &self.conn
+  }
+}
+
+impl Drop for Txn<'_> {
+  fn drop(&mut self) {
+    if self.open {
+      let _ = run(&self.conn, "ROLLBACK");
+    }
+  }
+}
+
+/// Runs a statement that takes no parameters, such as `BEGIN`, through the
+/// connection's statement cache.
+fn run(conn: &Connection, sql: &str) -> Result<(), Error> {
+  conn.prepare_cached(sql)?.execute([])?;
+  Ok(())
+}
@@ -0,0 +1,50 @@
+use std::thread;
+use std::time::Duration;
+use crate::Error;
+
+/// How `transaction` helpers retry when SQLite reports the database as busy or locked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+  /// Retries after the first attempt. Zero disables retrying.
+  pub max_retries: u32,
+  /// Delay before the first retry.
+  pub initial_backoff: Duration,
+  /// Upper bound for the delay, which doubles after every retry.
+  pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+  /// A policy that never retries.
+  pub fn none() -> Self {
+    RetryPolicy {
+      max_retries: 0,
+      initial_backoff: Duration::ZERO,
+      max_backoff: Duration::ZERO,
+    }
+  }
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy {
+      max_retries: 5,
+      initial_backoff: Duration::from_millis(10),
+      max_backoff: Duration::from_secs(1),
+    }
+  }
+}
+
+pub(crate) fn retry<T>(policy: &RetryPolicy, mut attempt: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+  let mut backoff = policy.initial_backoff;
+  let mut retries = 0;
+  loop {
+    match attempt() {
+      Err(e) if e.is_busy() && retries < policy.max_retries => {
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(policy.max_backoff);
+        retries += 1;
+      }
+      result => return result,
+    }
+  }
+}
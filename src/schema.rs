@@ -0,0 +1,595 @@
+//! Structural fingerprints of key and value types.
+//!
+//! A type is traced by deserializing it from a recording deserializer, which
+//! notes each call serde makes (primitives, options, sequences, structs with
+//! their field names, enums with their variants) and hands back placeholder
+//! values. Enums are traced one variant per pass until every reachable variant
+//! has been seen. The resulting shape ignores type names and module paths, so
+//! moving or renaming a type keeps its fingerprint while changing its fields
+//! does not.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::de::value::U32Deserializer;
+
+/// Nesting depth at which tracing gives up.
+const MAX_DEPTH: usize = 128;
+
+/// Returns the fingerprint of `T`'s serialized shape, or `None` if `T` cannot be
+/// traced, e.g. because it is self-describing or validates its input.
+pub(crate) fn fingerprint<T: DeserializeOwned>() -> Option<String> {
+  let mut tracer = Tracer::default();
+  let mut explored = 0;
+  loop {
+    let mut root = Shape::Unknown;
+    T::deserialize(Recorder { tracer: &mut tracer, shape: &mut root }).ok()?;
+    if !tracer.incomplete(&root, &mut Vec::new()) {
+      let mut desc = String::new();
+      tracer.describe(&root, &mut Vec::new(), &mut desc).ok()?;
+      return Some(format!("{:016x}", fnv1a(desc.as_bytes())));
+    }
+    let now = tracer.explored();
+    if now == explored {
+      return None;
+    }
+    explored = now;
+  }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+  bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Shape {
+  Unknown,
+  Unit,
+  Bool,
+  I8,
+  I16,
+  I32,
+  I64,
+  I128,
+  U8,
+  U16,
+  U32,
+  U64,
+  U128,
+  F32,
+  F64,
+  Char,
+  Str,
+  Bytes,
+  Option(Box<Shape>),
+  Seq(Box<Shape>),
+  Map(Box<Shape>, Box<Shape>),
+  Tuple(Vec<Shape>),
+  /// A struct or enum, recorded in `Tracer::containers` under its serde name.
+  Container(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Fields {
+  Unit,
+  Newtype(Shape),
+  Tuple(Vec<Shape>),
+  Named(Vec<(&'static str, Shape)>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Container {
+  Struct(Fields),
+  /// Variants in index order; `None` for variants not traced yet.
+  Enum(Vec<(&'static str, Option<Fields>)>),
+}
+
+#[derive(Default)]
+struct Tracer {
+  containers: BTreeMap<&'static str, Container>,
+  /// Containers being traced, with the variant chosen for enums.
+  stack: Vec<(&'static str, Option<u32>)>,
+  /// Set inside a recursive occurrence of a container. Its shape is already
+  /// being traced further up, so values are kept minimal to end the recursion
+  /// and nothing is recorded.
+  cut: bool,
+}
+
+impl Tracer {
+  fn explored(&self) -> usize {
+    self.containers.values().map(|c| match c {
+      Container::Struct(_) => 1,
+      Container::Enum(variants) => variants.iter().filter(|(_, f)| f.is_some()).count(),
+    }).sum()
+  }
+
+  /// Whether `shape` reaches an enum with untraced variants.
+  fn incomplete(&self, shape: &Shape, visited: &mut Vec<&'static str>) -> bool {
+    match shape {
+      Shape::Option(inner) | Shape::Seq(inner) => self.incomplete(inner, visited),
+      Shape::Map(key, value) => self.incomplete(key, visited) || self.incomplete(value, visited),
+      Shape::Tuple(shapes) => shapes.iter().any(|s| self.incomplete(s, visited)),
+      Shape::Container(name) => {
+        if visited.contains(name) {
+          return false;
+        }
+        visited.push(name);
+        match self.containers.get(name) {
+          None => true,
+          Some(Container::Struct(fields)) => self.fields_incomplete(fields, visited),
+          Some(Container::Enum(variants)) => variants.iter().any(|(_, f)| match f {
+            None => true,
+            Some(fields) => self.fields_incomplete(fields, visited),
+          }),
+        }
+      }
+      _ => false,
+    }
+  }
+
+  fn fields_incomplete(&self, fields: &Fields, visited: &mut Vec<&'static str>) -> bool {
+    match fields {
+      Fields::Unit => false,
+      Fields::Newtype(shape) => self.incomplete(shape, visited),
+      Fields::Tuple(shapes) => shapes.iter().any(|s| self.incomplete(s, visited)),
+      Fields::Named(named) => named.iter().any(|(_, s)| self.incomplete(s, visited)),
+    }
+  }
+
+  /// Picks the variant of enum `name` to trace next: an untraced one if any,
+  /// then one leading to untraced variants elsewhere. Inside a recursive
+  /// occurrence it prefers unit variants and variants not already being traced.
+  fn choose_variant(&self, name: &'static str, len: usize, cut: bool) -> u32 {
+    let traced: &[(&str, Option<Fields>)] = match self.containers.get(name) {
+      Some(Container::Enum(variants)) => variants,
+      _ => &[],
+    };
+    let fields = |i: usize| traced.get(i).and_then(|(_, f)| f.as_ref());
+    let index = if cut {
+      (0..len).find(|&i| fields(i) == Some(&Fields::Unit))
+        .or_else(|| (0..len).find(|&i| !self.stack.contains(&(name, Some(i as u32)))))
+    } else {
+      (0..len).find(|&i| fields(i).is_none())
+        .or_else(|| (0..len).find(|&i| fields(i).is_some_and(|f| self.fields_incomplete(f, &mut Vec::new()))))
+    };
+    index.unwrap_or(0) as u32
+  }
+
+  /// Traces the contents of struct `name` with `f`, recording the fields it returns.
+  fn trace_struct<T>(
+    &mut self,
+    name: &'static str,
+    f: impl FnOnce(&mut Tracer) -> Result<(T, Fields), TraceError>,
+  ) -> Result<T, TraceError> {
+    let cut = self.cut || self.stack.iter().any(|(n, _)| *n == name);
+    let (value, fields) = self.nested(name, None, cut, f)?;
+    if !cut {
+      self.record(name, Container::Struct(fields))?;
+    }
+    Ok(value)
+  }
+
+  fn nested<T>(
+    &mut self,
+    name: &'static str,
+    variant: Option<u32>,
+    cut: bool,
+    f: impl FnOnce(&mut Tracer) -> Result<T, TraceError>,
+  ) -> Result<T, TraceError> {
+    if self.stack.len() >= MAX_DEPTH {
+      return Err(de::Error::custom("type nests too deeply"));
+    }
+    let outer_cut = self.cut;
+    self.cut = cut;
+    self.stack.push((name, variant));
+    let result = f(self);
+    self.stack.pop();
+    self.cut = outer_cut;
+    result
+  }
+
+  fn record(&mut self, name: &'static str, container: Container) -> Result<(), TraceError> {
+    match self.containers.get(name) {
+      Some(existing) if *existing != container => Err(de::Error::custom(format!("conflicting shapes for {name}"))),
+      _ => {
+        self.containers.insert(name, container);
+        Ok(())
+      }
+    }
+  }
+
+  fn record_variant(
+    &mut self,
+    name: &'static str,
+    variants: &'static [&'static str],
+    index: u32,
+    fields: Fields,
+  ) -> Result<(), TraceError> {
+    let entry = self.containers.entry(name)
+      .or_insert_with(|| Container::Enum(variants.iter().map(|v| (*v, None)).collect()));
+    let Container::Enum(traced) = entry else {
+      return Err(de::Error::custom(format!("conflicting shapes for {name}")));
+    };
+    if traced.len() != variants.len() || traced.iter().zip(variants).any(|((a, _), b)| a != b) {
+      return Err(de::Error::custom(format!("conflicting shapes for {name}")));
+    }
+    match &mut traced[index as usize].1 {
+      Some(existing) if *existing != fields => Err(de::Error::custom(format!("conflicting shapes for {name}"))),
+      slot => {
+        *slot = Some(fields);
+        Ok(())
+      }
+    }
+  }
+
+  /// Writes the canonical description of `shape`. Container names are left
+  /// out; a recursive reference is written as the depth of the container it
+  /// refers to.
+  fn describe(&self, shape: &Shape, stack: &mut Vec<&'static str>, out: &mut String) -> fmt::Result {
+    match shape {
+      Shape::Unknown => out.write_str("?"),
+      Shape::Unit => out.write_str("unit"),
+      Shape::Bool => out.write_str("bool"),
+      Shape::I8 => out.write_str("i8"),
+      Shape::I16 => out.write_str("i16"),
+      Shape::I32 => out.write_str("i32"),
+      Shape::I64 => out.write_str("i64"),
+      Shape::I128 => out.write_str("i128"),
+      Shape::U8 => out.write_str("u8"),
+      Shape::U16 => out.write_str("u16"),
+      Shape::U32 => out.write_str("u32"),
+      Shape::U64 => out.write_str("u64"),
+      Shape::U128 => out.write_str("u128"),
+      Shape::F32 => out.write_str("f32"),
+      Shape::F64 => out.write_str("f64"),
+      Shape::Char => out.write_str("char"),
+      Shape::Str => out.write_str("str"),
+      Shape::Bytes => out.write_str("bytes"),
+      Shape::Option(inner) => {
+        out.write_str("option<")?;
+        self.describe(inner, stack, out)?;
+        out.write_str(">")
+      }
+      Shape::Seq(inner) => {
+        out.write_str("seq<")?;
+        self.describe(inner, stack, out)?;
+        out.write_str(">")
+      }
+      Shape::Map(key, value) => {
+        out.write_str("map<")?;
+        self.describe(key, stack, out)?;
+        out.write_str(",")?;
+        self.describe(value, stack, out)?;
+        out.write_str(">")
+      }
+      Shape::Tuple(shapes) => {
+        out.write_str("tuple")?;
+        self.describe_list(shapes, stack, out)
+      }
+      Shape::Container(name) => {
+        if let Some(depth) = stack.iter().position(|n| n == name) {
+          return write!(out, "#{depth}");
+        }
+        stack.push(name);
+        match &self.containers[name] {
+          Container::Struct(fields) => {
+            out.write_str("struct")?;
+            self.describe_fields(fields, stack, out)?;
+          }
+          Container::Enum(variants) => {
+            out.write_str("enum{")?;
+            for (i, (variant, fields)) in variants.iter().enumerate() {
+              if i > 0 {
+                out.write_str("|")?;
+              }
+              out.write_str(variant)?;
+              if let Some(fields) = fields {
+                self.describe_fields(fields, stack, out)?;
+              }
+            }
+            out.write_str("}")?;
+          }
+        }
+        stack.pop();
+        Ok(())
+      }
+    }
+  }
+
+  fn describe_fields(&self, fields: &Fields, stack: &mut Vec<&'static str>, out: &mut String) -> fmt::Result {
+    match fields {
+      Fields::Unit => Ok(()),
+      Fields::Newtype(shape) => self.describe_list(std::slice::from_ref(shape), stack, out),
+      Fields::Tuple(shapes) => self.describe_list(shapes, stack, out),
+      Fields::Named(named) => {
+        out.write_str("{")?;
+        for (i, (field, shape)) in named.iter().enumerate() {
+          if i > 0 {
+            out.write_str(",")?;
+          }
+          write!(out, "{field}:")?;
+          self.describe(shape, stack, out)?;
+        }
+        out.write_str("}")
+      }
+    }
+  }
+
+  fn describe_list(&self, shapes: &[Shape], stack: &mut Vec<&'static str>, out: &mut String) -> fmt::Result {
+    out.write_str("(")?;
+    for (i, shape) in shapes.iter().enumerate() {
+      if i > 0 {
+        out.write_str(",")?;
+      }
+      self.describe(shape, stack, out)?;
+    }
+    out.write_str(")")
+  }
+}
+
+#[derive(Debug)]
+struct TraceError(String);
+
+impl fmt::Display for TraceError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.0)
+  }
+}
+
+impl std::error::Error for TraceError {}
+
+impl de::Error for TraceError {
+  fn custom<T: fmt::Display>(msg: T) -> Self {
+    TraceError(msg.to_string())
+  }
+}
+
+/// Deserializer that records the shape requested of it into `shape`.
+struct Recorder<'a> {
+  tracer: &'a mut Tracer,
+  shape: &'a mut Shape,
+}
+
+macro_rules! record_primitive {
+  ($($method:ident => $shape:ident, $visit:ident($($value:expr)?);)*) => {
+    $(
+      fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.shape = Shape::$shape;
+        visitor.$visit($($value)?)
+      }
+    )*
+  };
+}
+
+impl<'de> de::Deserializer<'de> for Recorder<'_> {
+  type Error = TraceError;
+
+  fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+    Err(de::Error::custom("self-describing types have no fixed shape"))
+  }
+
+  record_primitive! {
+    deserialize_bool => Bool, visit_bool(false);
+    deserialize_i8 => I8, visit_i8(0);
+    deserialize_i16 => I16, visit_i16(0);
+    deserialize_i32 => I32, visit_i32(0);
+    deserialize_i64 => I64, visit_i64(0);
+    deserialize_i128 => I128, visit_i128(0);
+    deserialize_u8 => U8, visit_u8(0);
+    deserialize_u16 => U16, visit_u16(0);
+    deserialize_u32 => U32, visit_u32(0);
+    deserialize_u64 => U64, visit_u64(0);
+    deserialize_u128 => U128, visit_u128(0);
+    deserialize_f32 => F32, visit_f32(0.0);
+    deserialize_f64 => F64, visit_f64(0.0);
+    deserialize_char => Char, visit_char('\0');
+    deserialize_str => Str, visit_str("");
+    deserialize_string => Str, visit_string(String::new());
+    deserialize_bytes => Bytes, visit_bytes(&[]);
+    deserialize_byte_buf => Bytes, visit_byte_buf(Vec::new());
+    deserialize_unit => Unit, visit_unit();
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+    let Recorder { tracer, shape } = self;
+    let mut inner = Shape::Unknown;
+    let value = if tracer.cut {
+      visitor.visit_none()?
+    } else {
+      visitor.visit_some(Recorder { tracer, shape: &mut inner })?
+    };
+    *shape = Shape::Option(Box::new(inner));
+    Ok(value)
+  }
+
+  fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, TraceError> {
+    *self.shape = Shape::Container(name);
+    self.tracer.trace_struct(name, |_| Ok((visitor.visit_unit()?, Fields::Unit)))
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value, TraceError> {
+    *self.shape = Shape::Container(name);
+    self.tracer.trace_struct(name, |tracer| {
+      let mut inner = Shape::Unknown;
+      let value = visitor.visit_newtype_struct(Recorder { tracer, shape: &mut inner })?;
+      Ok((value, Fields::Newtype(inner)))
+    })
+  }
+
+  fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+    let Recorder { tracer, shape } = self;
+    let mut element = vec![Shape::Unknown; if tracer.cut { 0 } else { 1 }];
+    let value = visitor.visit_seq(Elements { tracer, shapes: element.iter_mut() })?;
+    *shape = Shape::Seq(Box::new(element.pop().unwrap_or(Shape::Unknown)));
+    Ok(value)
+  }
+
+  fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+    let Recorder { tracer, shape } = self;
+    let (value, shapes) = trace_elements(tracer, len, visitor)?;
+    *shape = Shape::Tuple(shapes);
+    Ok(value)
+  }
+
+  fn deserialize_tuple_struct<V: Visitor<'de>>(
+    self,
+    name: &'static str,
+    len: usize,
+    visitor: V,
+  ) -> Result<V::Value, TraceError> {
+    *self.shape = Shape::Container(name);
+    self.tracer.trace_struct(name, |tracer| {
+      let (value, shapes) = trace_elements(tracer, len, visitor)?;
+      Ok((value, Fields::Tuple(shapes)))
+    })
+  }
+
+  fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+    let Recorder { tracer, shape } = self;
+    let (mut key, mut val) = (Shape::Unknown, Shape::Unknown);
+    let remaining = if tracer.cut { 0 } else { 1 };
+    let value = visitor.visit_map(Entries { tracer, key: &mut key, value: &mut val, remaining })?;
+    *shape = Shape::Map(Box::new(key), Box::new(val));
+    Ok(value)
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(
+    self,
+    name: &'static str,
+    fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, TraceError> {
+    *self.shape = Shape::Container(name);
+    self.tracer.trace_struct(name, |tracer| {
+      let (value, shapes) = trace_elements(tracer, fields.len(), visitor)?;
+      Ok((value, Fields::Named(fields.iter().copied().zip(shapes).collect())))
+    })
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    name: &'static str,
+    variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, TraceError> {
+    let Recorder { tracer, shape } = self;
+    *shape = Shape::Container(name);
+    if variants.is_empty() {
+      return Err(de::Error::custom(format!("enum {name} has no variants")));
+    }
+    let cut = tracer.cut || tracer.stack.iter().any(|(n, _)| *n == name);
+    let index = tracer.choose_variant(name, variants.len(), cut);
+    let mut fields = Fields::Unit;
+    let value = tracer.nested(name, Some(index), cut, |tracer| {
+      visitor.visit_enum(Variant { tracer, index, fields: &mut fields })
+    })?;
+    if !cut {
+      tracer.record_variant(name, variants, index, fields)?;
+    }
+    Ok(value)
+  }
+
+  fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+    Err(de::Error::custom("identifiers have no fixed shape"))
+  }
+
+  fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+    Err(de::Error::custom("ignored values have no fixed shape"))
+  }
+
+  fn is_human_readable(&self) -> bool {
+    false
+  }
+}
+
+fn trace_elements<'de, V: Visitor<'de>>(tracer: &mut Tracer, len: usize, visitor: V) -> Result<(V::Value, Vec<Shape>), TraceError> {
+  let mut shapes = vec![Shape::Unknown; len];
+  let value = visitor.visit_seq(Elements { tracer, shapes: shapes.iter_mut() })?;
+  Ok((value, shapes))
+}
+
+/// Hands out one element per shape slot.
+struct Elements<'a, 'b> {
+  tracer: &'a mut Tracer,
+  shapes: std::slice::IterMut<'b, Shape>,
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, '_> {
+  type Error = TraceError;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, TraceError> {
+    match self.shapes.next() {
+      Some(shape) => seed.deserialize(Recorder { tracer: self.tracer, shape }).map(Some),
+      None => Ok(None),
+    }
+  }
+}
+
+/// Hands out `remaining` (zero or one) map entries.
+struct Entries<'a> {
+  tracer: &'a mut Tracer,
+  key: &'a mut Shape,
+  value: &'a mut Shape,
+  remaining: usize,
+}
+
+impl<'de> de::MapAccess<'de> for Entries<'_> {
+  type Error = TraceError;
+
+  fn next_key_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, TraceError> {
+    if self.remaining == 0 {
+      return Ok(None);
+    }
+    self.remaining -= 1;
+    seed.deserialize(Recorder { tracer: self.tracer, shape: self.key }).map(Some)
+  }
+
+  fn next_value_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value, TraceError> {
+    seed.deserialize(Recorder { tracer: self.tracer, shape: self.value })
+  }
+}
+
+/// Selects variant `index` of an enum and records the fields it is read with.
+struct Variant<'a> {
+  tracer: &'a mut Tracer,
+  index: u32,
+  fields: &'a mut Fields,
+}
+
+impl<'de> de::EnumAccess<'de> for Variant<'_> {
+  type Error = TraceError;
+  type Variant = Self;
+
+  fn variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<(T::Value, Self), TraceError> {
+    let index: U32Deserializer<TraceError> = self.index.into_deserializer();
+    Ok((seed.deserialize(index)?, self))
+  }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'_> {
+  type Error = TraceError;
+
+  fn unit_variant(self) -> Result<(), TraceError> {
+    *self.fields = Fields::Unit;
+    Ok(())
+  }
+
+  fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, TraceError> {
+    let mut inner = Shape::Unknown;
+    let value = seed.deserialize(Recorder { tracer: self.tracer, shape: &mut inner })?;
+    *self.fields = Fields::Newtype(inner);
+    Ok(value)
+  }
+
+  fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+    let (value, shapes) = trace_elements(self.tracer, len, visitor)?;
+    *self.fields = Fields::Tuple(shapes);
+    Ok(value)
+  }
+
+  fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value, TraceError> {
+    let (value, shapes) = trace_elements(self.tracer, fields.len(), visitor)?;
+    *self.fields = Fields::Named(fields.iter().copied().zip(shapes).collect());
+    Ok(value)
+  }
+}
@@ -0,0 +1,105 @@
+//! Change notifications, see `Collection::subscribe`.
+//!
+//! Writes record their changes in the transaction, with keys and values in
+//! encoded form, and only when the collection has subscribers. Committing the
+//! transaction hands them to `Subscribers::notify`, which decodes them for each
+//! subscriber; rolling back discards them.
+
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::{RwLock, mpsc};
+use crate::Error;
+use crate::codec::ValueCodec;
+use crate::key_codec;
+
+/// A change made to a collection by a committed transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<K, V> {
+  /// An entry was added.
+  Insert { key: K, value: V },
+  /// The value of an existing entry was replaced.
+  Update { key: K, value: V },
+  /// An entry was deleted.
+  Delete { key: K },
+  /// Every entry was deleted with `clear`.
+  Clear,
+}
+
+impl<K, V> Change<K, V> {
+  /// The key changed, or `None` for `Clear`.
+  pub fn key(&self) -> Option<&K> {
+    match self {
+      Change::Insert { key, .. } | Change::Update { key, .. } | Change::Delete { key } => Some(key),
+      Change::Clear => None,
+    }
+  }
+}
+
+/// A change with its key encoded and its value serialized without framing.
+pub(crate) type RawChange = Change<Vec<u8>, Vec<u8>>;
+
+fn decode<K: DeserializeOwned, V>(raw: &RawChange, codec: &ValueCodec<V>) -> Result<Change<K, V>, Error> {
+  Ok(match raw {
+    Change::Insert { key, value } => Change::Insert { key: key_codec::decode_key(key)?, value: codec.deserialize(value)? },
+    Change::Update { key, value } => Change::Update { key: key_codec::decode_key(key)?, value: codec.deserialize(value)? },
+    Change::Delete { key } => Change::Delete { key: key_codec::decode_key(key)? },
+    Change::Clear => Change::Clear,
+  })
+}
+
+struct Subscriber {
+  /// Encoded key of a `watch`, which only receives changes to that key.
+  key: Option<Vec<u8>>,
+  /// Sends a change on, returning `false` once the receiver is gone.
+  send: Box<dyn Fn(&RawChange) -> bool + Send + Sync>,
+}
+
+/// The subscribers of each collection.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+  collections: RwLock<HashMap<String, Vec<Subscriber>>>,
+}
+
+impl Subscribers {
+  /// Adds a subscriber to a collection, optionally for a single encoded key.
+  pub(crate) fn add<K, V>(&self, collection: &str, key: Option<Vec<u8>>, codec: ValueCodec<V>) -> mpsc::Receiver<Change<K, V>>
+  where
+    K: DeserializeOwned + Send + 'static,
+    V: Send + 'static,
+  {
+    let (sender, receiver) = mpsc::channel();
+    // Changes that do not decode as `K` and `V` are skipped.
+    let send = move |raw: &RawChange| match decode(raw, &codec) {
+      Ok(change) => sender.send(change).is_ok(),
+      Err(_) => true,
+    };
+    let mut collections = self.collections.write().unwrap_or_else(|e| e.into_inner());
+    collections.entry(collection.to_string()).or_default().push(Subscriber { key, send: Box::new(send) });
+    receiver
+  }
+
+  pub(crate) fn watched(&self, collection: &str) -> bool {
+    self.collections.read().unwrap_or_else(|e| e.into_inner()).contains_key(collection)
+  }
+
+  /// Sends committed changes to the subscribers of their collections, and
+  /// drops the subscribers whose receivers are gone.
+  pub(crate) fn notify(&self, changes: Vec<(String, RawChange)>) {
+    if changes.is_empty() {
+      return;
+    }
+    let mut collections = self.collections.write().unwrap_or_else(|e| e.into_inner());
+    for (collection, change) in changes {
+      let Some(subscribers) = collections.get_mut(&collection) else {
+        continue;
+      };
+      subscribers.retain(|s| match (&s.key, change.key()) {
+        (Some(watched), Some(key)) if watched != key => true,
+        _ => (s.send)(&change),
+      });
+      if subscribers.is_empty() {
+        collections.remove(&collection);
+      }
+    }
+  }
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "async")]
+
+use storedb::{AsyncDatabase, Error};
+use tempfile::NamedTempFile;
+
+#[tokio::test]
+async fn test_async_transaction_round_trip() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = AsyncDatabase::new(temp_file.path()).await?;
+  let users = db.get_collection::<u32, String>("users").await?;
+
+  let mut tx = users.begin().await?;
+  tx.put(1u32, "alice").await?;
+  tx.set(2u32, "bob").await?;
+  assert!(matches!(tx.put(1u32, "again").await, Err(Error::KeyAlreadyExists)));
+  assert_eq!(tx.count().await?, 2);
+  tx.commit().await?;
+
+  let tx = users.read().await?;
+  assert_eq!(tx.get(1u32).await?, Some("alice".to_string()));
+  assert_eq!(tx.scan().await?, vec![(1, "alice".to_string()), (2, "bob".to_string())]);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_async_rollback_and_drop() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = AsyncDatabase::new(temp_file.path()).await?;
+  let items = db.get_collection::<u32, u32>("items").await?;
+
+  let mut tx = items.begin().await?;
+  tx.set(1u32, 1u32).await?;
+  tx.rollback().await?;
+
+  // Dropping an open transaction rolls it back and frees the writer.
+  let mut tx = items.begin().await?;
+  tx.set(2u32, 2u32).await?;
+  drop(tx);
+
+  let n = items.transaction(|tx| {
+    tx.set(3u32, 3u32)?;
+    tx.count()
+  }).await?;
+  assert_eq!(n, 1);
+  assert_eq!(items.read().await?.keys().await?, vec![3]);
+  Ok(())
+}
+
+#[tokio::test]
+async fn test_async_database_transaction() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = AsyncDatabase::new(temp_file.path()).await?;
+  let accounts = db.get_collection::<String, i64>("accounts").await?;
+  accounts.transaction(|tx| tx.set("alice", 100)).await?;
+
+  let mut tx = db.begin().await?;
+  tx.run(|tx| {
+    let mut accounts = tx.collection::<String, i64>("accounts")?;
+    accounts.set("alice", 60)?;
+    tx.collection::<String, i64>("audit")?.set("alice", -40)
+  }).await?;
+  let balance = tx.run(|tx| tx.collection::<String, i64>("accounts")?.get("alice")).await?;
+  assert_eq!(balance, Some(60));
+  tx.commit().await?;
+
+  let mut tx = db.begin().await?;
+  tx.run(|tx| tx.collection::<String, i64>("accounts")?.set("alice", 0)).await?;
+  tx.rollback().await?;
+
+  assert_eq!(accounts.read().await?.get("alice").await?, Some(60));
+  let audit = db.get_collection::<String, i64>("audit").await?;
+  assert_eq!(audit.read().await?.scan().await?, vec![("alice".to_string(), -40)]);
+  Ok(())
+}
@@ -11,9 +11,9 @@ struct TestVal {
 fn test_collection_creation_and_use() -> Result<(), Error> {
   let temp_file = NamedTempFile::new().unwrap();
   let db_path = temp_file.path().to_str().unwrap();
-  let mut db = Database::new(db_path)?;
+  let db = Database::new(db_path)?;
 
-  let mut coll = db.get_collection::<String, String>("test_coll")?;
+  let coll = db.get_collection::<String, String>("test_coll")?;
 
   {
     let mut tx = coll.begin()?;
@@ -34,10 +34,10 @@ fn test_collection_creation_and_use() -> Result<(), Error> {
 fn test_collection_type_mismatch() -> Result<(), Error> {
   let temp_file = NamedTempFile::new().unwrap();
   let db_path = temp_file.path().to_str().unwrap();
-  let mut db = Database::new(db_path)?;
+  let db = Database::new(db_path)?;
 
   // Create with (String, String)
-  let mut coll = db.get_collection::<String,String>("mismatch")?;
+  let coll = db.get_collection::<String,String>("mismatch")?;
   {
     let mut tx = coll.begin()?;
     tx.set("k".to_string(), "v".to_string())?;
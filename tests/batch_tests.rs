@@ -0,0 +1,44 @@
+use storedb::{Database, Error};
+
+#[test]
+fn test_set_get_and_del_many() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let coll = db.get_collection::<u32, String>("bulk")?;
+
+  // More entries than fit in one statement.
+  let mut tx = coll.begin()?;
+  tx.set_many((0..1000u32).map(|i| (i, i.to_string())))?;
+  tx.commit()?;
+
+  let tx = coll.read()?;
+  assert_eq!(tx.count()?, 1000);
+  let values = tx.get_many(&[5, 2000, 999, 5])?;
+  assert_eq!(values, vec![Some("5".to_string()), None, Some("999".to_string()), Some("5".to_string())]);
+  drop(tx);
+
+  let mut tx = coll.begin()?;
+  let keys: Vec<u32> = (0..1000).filter(|i| i % 2 == 0).chain([5000]).collect();
+  assert_eq!(tx.del_many(&keys)?, 500);
+  assert_eq!(tx.count()?, 500);
+  assert_eq!(tx.get_many(&[0, 1])?, vec![None, Some("1".to_string())]);
+  tx.commit()?;
+  Ok(())
+}
+
+#[test]
+fn test_put_many_reports_existing_keys() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let coll = db.get_collection::<String, u32>("users")?;
+  let mut tx = coll.begin()?;
+  tx.set("bob", 1u32)?;
+
+  let existing = tx.put_many([("alice", 10u32), ("bob", 20), ("carol", 30), ("alice", 40)])?;
+  assert_eq!(existing, vec!["bob".to_string(), "alice".to_string()]);
+  assert_eq!(
+    tx.scan()?,
+    vec![("alice".to_string(), 10), ("bob".to_string(), 1), ("carol".to_string(), 30)],
+  );
+  assert!(tx.put_many(Vec::<(String, u32)>::new())?.is_empty());
+  tx.commit()?;
+  Ok(())
+}
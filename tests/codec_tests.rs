@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use storedb::{Codec, Database, Error, Postcard};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+struct Doc {
+  title: String,
+  tags: Vec<String>,
+}
+
+fn doc(title: &str) -> Doc {
+  Doc { title: title.into(), tags: vec!["a".into(), "b".into()] }
+}
+
+/// postcard with the bytes reversed, to stand in for an application's own format.
+struct Reversed;
+
+impl Codec for Reversed {
+  const NAME: &'static str = "reversed";
+
+  fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    let mut bytes = Postcard::encode(value)?;
+    bytes.reverse();
+    Ok(bytes)
+  }
+
+  fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let mut bytes = bytes.to_vec();
+    bytes.reverse();
+    Postcard::decode(&bytes)
+  }
+}
+
+#[test]
+fn test_custom_codec_is_recorded_and_checked() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let docs = db.collection_builder::<u32, Doc>("docs").codec(Reversed).open()?;
+  docs.transaction(|tx| tx.set(1u32, doc("one")))?;
+  assert_eq!(docs.read()?.iter_values().collect::<Result<Vec<_>, _>>()?, vec![doc("one")]);
+  assert_eq!(db.list_collections()?[0].codec, "reversed");
+
+  // Custom codecs cannot be found by name, so they must be given on every open.
+  assert!(matches!(db.get_collection::<u32, Doc>("docs"), Err(Error::UnknownCodec(_))));
+  assert!(matches!(
+    db.collection_builder::<u32, Doc>("docs").codec(Postcard).open(),
+    Err(Error::CodecMismatch { .. })
+  ));
+  let docs = db.collection_builder::<u32, Doc>("docs").codec(Reversed).open()?;
+  assert_eq!(docs.read()?.get(1u32)?, Some(doc("one")));
+  Ok(())
+}
+
+#[test]
+fn test_custom_codec_in_database_transactions_and_migrations() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  db.collection_builder::<u32, Doc>("docs").codec(Reversed).open()?;
+  db.transaction(|tx| {
+    assert!(matches!(tx.collection::<u32, Doc>("docs"), Err(Error::UnknownCodec(_))));
+    let mut docs = tx.collection_with_codec::<u32, Doc, _>("docs", Reversed)?;
+    docs.set(1u32, doc("one"))?;
+    docs.set(2u32, doc("two"))
+  })?;
+
+  assert!(matches!(
+    db.migrate_collection::<u32, Doc, u32, String>("docs", |(id, d)| (id, d.title)),
+    Err(Error::UnknownCodec(_))
+  ));
+  let version = db.migrate_collection_with_codec::<u32, Doc, u32, String, _>("docs", Reversed, |(id, d)| (id, d.title))?;
+  assert_eq!(version, 1);
+  let titles = db.collection_builder::<u32, String>("docs").codec(Reversed).open()?;
+  assert_eq!(titles.read()?.scan()?, vec![(1, "one".to_string()), (2, "two".to_string())]);
+  Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json_values_are_readable_from_sql() -> Result<(), Error> {
+  use storedb::Json;
+
+  let db = Database::temporary()?;
+  let docs = db.collection_builder::<u32, Doc>("docs").codec(Json).open()?;
+  docs.transaction(|tx| {
+    tx.set(1u32, doc("one"))?;
+    tx.put(2u32, doc("two"))
+  })?;
+
+  let conn = rusqlite::Connection::open(db.path().unwrap())?;
+  let titles: Vec<String> = conn
+    .prepare("SELECT json_extract(value, '$.title') FROM kv_store WHERE collection = 'docs' ORDER BY key")?
+    .query_map([], |row| row.get(0))?
+    .collect::<Result<_, _>>()?;
+  assert_eq!(titles, vec!["one", "two"]);
+
+  // The recorded codec is picked up when none is given.
+  let docs = db.get_collection::<u32, Doc>("docs")?;
+  assert_eq!(docs.read()?.scan()?, vec![(1, doc("one")), (2, doc("two"))]);
+  db.transaction(|tx| {
+    let docs = tx.collection::<u32, Doc>("docs")?;
+    assert_eq!(docs.get(2u32)?, Some(doc("two")));
+    Ok(())
+  })?;
+  Ok(())
+}
+
+#[cfg(all(feature = "cbor", feature = "bincode"))]
+#[test]
+fn test_binary_codecs_round_trip() -> Result<(), Error> {
+  use storedb::{Bincode, Cbor};
+
+  let db = Database::temporary()?;
+  let cbor = db.collection_builder::<u32, Doc>("cbor").codec(Cbor).open()?;
+  let bincode = db.collection_builder::<u32, Doc>("bincode").codec(Bincode).open()?;
+  cbor.transaction(|tx| tx.set(1u32, doc("one")))?;
+  bincode.transaction(|tx| tx.set(1u32, doc("one")))?;
+
+  assert_eq!(db.get_collection::<u32, Doc>("cbor")?.read()?.get(1u32)?, Some(doc("one")));
+  assert_eq!(db.get_collection::<u32, Doc>("bincode")?.read()?.get(1u32)?, Some(doc("one")));
+  let codecs: Vec<String> = db.list_collections()?.into_iter().map(|c| c.codec).collect();
+  assert_eq!(codecs, vec!["bincode", "cbor"]);
+  Ok(())
+}
@@ -0,0 +1,101 @@
+#![cfg(any(feature = "zstd", feature = "lz4"))]
+
+use storedb::{Compression, Database, Error};
+
+fn stored_sizes(db: &Database, collection: &str) -> Result<Vec<(String, i64)>, Error> {
+  let conn = rusqlite::Connection::open(db.path().unwrap())?;
+  let mut stmt = conn.prepare("SELECT typeof(value), length(value) FROM kv_store WHERE collection = ? ORDER BY key")?;
+  let sizes = stmt.query_map([collection], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<_, _>>()?;
+  Ok(sizes)
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_large_values_are_compressed() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let docs = db.collection_builder::<u32, String>("docs").compression(Compression::Zstd).open()?;
+  let large = "lorem ipsum ".repeat(500);
+  docs.transaction(|tx| {
+    tx.set(1u32, large.clone())?;
+    tx.set(2u32, "short")
+  })?;
+
+  let sizes = stored_sizes(&db, "docs")?;
+  assert!(sizes[0].1 < 200, "large value stored in {} bytes", sizes[0].1);
+  // Below the threshold: postcard's length byte and "short", plus the header.
+  assert_eq!(sizes[1].1, 7);
+
+  // The setting is picked up again on open.
+  let docs = db.get_collection::<u32, String>("docs")?;
+  assert_eq!(docs.read()?.get(1u32)?, Some(large.clone()));
+  assert_eq!(docs.read()?.scan()?, vec![(1, large), (2, "short".to_string())]);
+  assert_eq!(db.list_collections()?[0].compression, "zstd");
+  Ok(())
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn test_compression_can_be_changed() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let docs = db.get_collection::<u32, String>("docs")?;
+  // Enough entries to be reframed in several batches.
+  let values: Vec<(u32, String)> = (0..600u32).map(|i| (i, format!("{i}-").repeat((i % 20) as usize * 20))).collect();
+  docs.transaction(|tx| {
+    for (k, v) in &values {
+      tx.set(*k, v.clone())?;
+    }
+    Ok(())
+  })?;
+  let plain: i64 = stored_sizes(&db, "docs")?.iter().map(|(_, len)| len).sum();
+
+  let docs = db.collection_builder::<u32, String>("docs")
+    .compression(Compression::Lz4)
+    .compression_threshold(64)
+    .open()?;
+  assert_eq!(docs.read()?.scan()?, values);
+  let compressed: i64 = stored_sizes(&db, "docs")?.iter().map(|(_, len)| len).sum();
+  assert!(compressed < plain / 2, "{compressed} vs {plain}");
+
+  let docs = db.collection_builder::<u32, String>("docs").compression(Compression::None).open()?;
+  assert_eq!(docs.read()?.scan()?, values);
+  let restored: i64 = stored_sizes(&db, "docs")?.iter().map(|(_, len)| len).sum();
+  assert_eq!(restored, plain);
+  Ok(())
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_open_handles_follow_compression_changes() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let old = db.get_collection::<u32, String>("docs")?;
+  old.transaction(|tx| tx.set(1u32, "a".repeat(1000)))?;
+
+  let new = db.collection_builder::<u32, String>("docs").compression(Compression::Zstd).open()?;
+  assert_eq!(old.read()?.get(1u32)?, Some("a".repeat(1000)));
+  old.transaction(|tx| tx.set(2u32, "b".repeat(1000)))?;
+  assert_eq!(new.read()?.get(2u32)?, Some("b".repeat(1000)));
+  assert!(stored_sizes(&db, "docs")?.iter().all(|(_, len)| *len < 100));
+
+  db.collection_builder::<u32, String>("docs").compression(Compression::None).open()?;
+  new.transaction(|tx| tx.set(3u32, "c".repeat(1000)))?;
+  assert_eq!(old.read()?.scan()?.len(), 3);
+  Ok(())
+}
+
+#[cfg(all(feature = "json", feature = "zstd"))]
+#[test]
+fn test_small_json_values_stay_text() -> Result<(), Error> {
+  use storedb::Json;
+
+  let db = Database::temporary()?;
+  let docs = db.collection_builder::<u32, Vec<String>>("docs").codec(Json).compression(Compression::Zstd).open()?;
+  docs.transaction(|tx| {
+    tx.set(1u32, vec!["a".to_string()])?;
+    tx.set(2u32, vec!["abc".to_string(); 200])
+  })?;
+
+  let types: Vec<String> = stored_sizes(&db, "docs")?.into_iter().map(|(t, _)| t).collect();
+  assert_eq!(types, vec!["text", "blob"]);
+  assert_eq!(docs.read()?.get(2u32)?, Some(vec!["abc".to_string(); 200]));
+  Ok(())
+}
@@ -0,0 +1,100 @@
+use std::thread;
+use storedb::{Collection, Database, Error};
+use tempfile::NamedTempFile;
+
+fn assert_send_sync_clone<T: Send + Sync + Clone>() {}
+
+#[test]
+fn test_handles_are_send_sync_clone() {
+  assert_send_sync_clone::<Database>();
+  assert_send_sync_clone::<Collection<String, Vec<u8>>>();
+}
+
+#[test]
+fn test_parallel_writers_are_serialized() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let counters = db.get_collection::<String, u64>("counters")?;
+
+  let workers: Vec<_> = (0..8)
+    .map(|_| {
+      let counters = counters.clone();
+      thread::spawn(move || -> Result<(), Error> {
+        for _ in 0..25 {
+          counters.transaction(|tx| {
+            let n = tx.get("hits")?.unwrap_or(0);
+            tx.set("hits", n + 1)
+          })?;
+        }
+        Ok(())
+      })
+    })
+    .collect();
+  for worker in workers {
+    worker.join().unwrap()?;
+  }
+
+  assert_eq!(counters.read()?.get("hits")?, Some(200));
+  Ok(())
+}
+
+#[test]
+fn test_reader_keeps_snapshot_while_writer_commits() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, u32>("items")?;
+  coll.transaction(|tx| tx.set(1u32, 1u32))?;
+
+  let reader = coll.read()?;
+  assert_eq!(reader.get(1u32)?, Some(1));
+
+  let writer = coll.clone();
+  thread::spawn(move || writer.transaction(|tx| tx.set(1u32, 2u32))).join().unwrap()?;
+
+  assert_eq!(reader.get(1u32)?, Some(1));
+  drop(reader);
+  assert_eq!(coll.read()?.get(1u32)?, Some(2));
+  Ok(())
+}
+
+#[test]
+fn test_nested_write_transaction_is_rejected() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, u32>("items")?;
+
+  let tx = coll.begin()?;
+  assert!(matches!(coll.begin(), Err(Error::NestedTransaction)));
+  assert!(matches!(db.begin(), Err(Error::NestedTransaction)));
+  drop(tx);
+  assert!(coll.begin().is_ok());
+  Ok(())
+}
+
+#[test]
+fn test_concurrent_opens_upgrade_schema_once() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  {
+    // A database as storedb 2.0 wrote it, in WAL mode like every open does.
+    let conn = rusqlite::Connection::open(temp_file.path())?;
+    conn.pragma_update(None, "journal_mode", "wal")?;
+    conn.execute_batch(r#"
+      CREATE TABLE collection_meta (name TEXT PRIMARY KEY, key_type TEXT NOT NULL, value_type TEXT NOT NULL);
+      CREATE TABLE kv_store (collection TEXT, key BLOB, value BLOB NOT NULL, PRIMARY KEY(collection, key));
+    "#)?;
+  }
+
+  let openers: Vec<_> = (0..8)
+    .map(|_| {
+      let path = temp_file.path().to_path_buf();
+      thread::spawn(move || Database::new(path).map(drop))
+    })
+    .collect();
+  for opener in openers {
+    opener.join().unwrap()?;
+  }
+
+  let db = Database::new(temp_file.path())?;
+  db.get_collection::<u32, u32>("items")?.transaction(|tx| tx.set(1u32, 1u32))?;
+  Ok(())
+}
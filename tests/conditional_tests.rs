@@ -0,0 +1,47 @@
+use storedb::{Database, Error};
+
+#[test]
+fn test_compare_and_swap() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let counters = db.get_collection::<String, u64>("counters")?;
+  let mut tx = counters.begin()?;
+
+  // `None` expects the key to be absent.
+  tx.compare_and_swap("hits", None, Some(1))?;
+  tx.compare_and_swap("hits", Some(1), Some(2))?;
+  assert_eq!(tx.get("hits")?, Some(2));
+
+  let err = tx.compare_and_swap("hits", Some(1), Some(5)).unwrap_err();
+  assert!(matches!(err, Error::ConditionFailed { .. }));
+  assert_eq!(err.current_value::<u64>(), Some(&2));
+  assert_eq!(tx.get("hits")?, Some(2));
+
+  let err = tx.compare_and_swap("misses", Some(0), Some(1)).unwrap_err();
+  assert!(matches!(err, Error::ConditionFailed { current: None }));
+
+  // A `None` replacement deletes the entry.
+  tx.compare_and_swap("hits", Some(2), None)?;
+  assert!(!tx.contains("hits")?);
+  tx.commit()?;
+  Ok(())
+}
+
+#[test]
+fn test_update_if() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let stock = db.get_collection::<u32, i32>("stock")?;
+  stock.transaction(|tx| tx.set(1u32, 3))?;
+
+  let take = |tx: &mut storedb::CollectionTx<'_, u32, i32>| {
+    let left = tx.get(1u32)?.unwrap_or(0);
+    tx.update_if(1u32, |current| current.is_some_and(|n| *n > 0), left - 1)
+  };
+  for _ in 0..3 {
+    stock.transaction(take)?;
+  }
+  let err = stock.transaction(take).unwrap_err();
+  assert_eq!(err.current_value::<i32>(), Some(&0));
+  assert_eq!(err.current_value::<u32>(), None);
+  assert_eq!(stock.read()?.get(1u32)?, Some(0));
+  Ok(())
+}
@@ -0,0 +1,45 @@
+use storedb::{Database, Error};
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_commit_across_collections() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+
+  let tx = db.begin()?;
+  let mut users = tx.collection::<u32, String>("users")?;
+  let mut accounts = tx.collection::<u32, i64>("accounts")?;
+  users.set(1u32, "alice")?;
+  accounts.set(1u32, 100i64)?;
+  accounts.set(2u32, -5i64)?;
+  assert_eq!(users.get(1u32)?, Some("alice".to_string()));
+  drop((users, accounts));
+  tx.commit()?;
+
+  let users = db.get_collection::<u32, String>("users")?;
+  assert_eq!(users.begin()?.get(1u32)?, Some("alice".to_string()));
+  let accounts = db.get_collection::<u32, i64>("accounts")?;
+  assert_eq!(accounts.begin()?.count()?, 2);
+  Ok(())
+}
+
+#[test]
+fn test_rollback_across_collections() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+
+  let tx = db.begin()?;
+  {
+    let mut users = tx.collection::<u32, String>("users")?;
+    let mut accounts = tx.collection::<u32, i64>("accounts")?;
+    users.set(1u32, "alice")?;
+    accounts.set(1u32, 100i64)?;
+  }
+  tx.rollback()?;
+
+  let tx = db.begin()?;
+  assert_eq!(tx.collection::<u32, String>("users")?.count()?, 0);
+  assert_eq!(tx.collection::<u32, i64>("accounts")?.count()?, 0);
+  assert!(matches!(tx.collection::<String, i64>("accounts"), Err(Error::TypeMismatch { .. })));
+  Ok(())
+}
@@ -0,0 +1,176 @@
+#![cfg(feature = "encryption")]
+
+use std::path::Path;
+use storedb::{Database, EncryptionKey, Error};
+use tempfile::TempDir;
+
+type Row = (Vec<u8>, Vec<u8>);
+
+fn stored_rows(path: &Path) -> Result<Vec<Row>, Error> {
+  let conn = rusqlite::Connection::open(path)?;
+  let mut stmt = conn.prepare("SELECT key, value FROM kv_store ORDER BY key")?;
+  let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<_, _>>()?;
+  Ok(rows)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+  haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[test]
+fn test_values_are_encrypted_at_rest() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("enc.db");
+  let key = EncryptionKey::generate();
+  {
+    let db = Database::builder(&path).encryption_key(key.clone()).open()?;
+    let people = db.get_collection::<String, String>("people")?;
+    people.transaction(|tx| tx.set("alice", "alice@example.com"))?;
+    assert_eq!(people.read()?.get("alice")?, Some("alice@example.com".to_string()));
+  }
+
+  let rows = stored_rows(&path)?;
+  assert_eq!(rows.len(), 1);
+  assert!(!contains(&rows[0].1, b"example.com"));
+
+  assert!(matches!(Database::new(&path), Err(Error::EncryptionKeyRequired)));
+  let wrong = Database::builder(&path).encryption_key(EncryptionKey::generate()).open();
+  assert!(matches!(wrong, Err(Error::WrongEncryptionKey)));
+
+  let db = Database::builder(&path).encryption_key(key).read_only(true).open()?;
+  let people = db.get_collection::<String, String>("people")?;
+  assert_eq!(people.read()?.scan()?, vec![("alice".to_string(), "alice@example.com".to_string())]);
+  Ok(())
+}
+
+#[test]
+fn test_existing_database_is_encrypted_and_key_rotated() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("enc.db");
+  {
+    let db = Database::new(&path)?;
+    let notes = db.get_collection::<u32, String>("notes")?;
+    notes.transaction(|tx| {
+      tx.set(1u32, "first note")?;
+      tx.set(2u32, "second note")
+    })?;
+  }
+
+  let old_key = EncryptionKey::new([7; 32]);
+  let new_key = EncryptionKey::new([9; 32]);
+  {
+    let db = Database::builder(&path).encryption_key(old_key.clone()).open()?;
+    assert!(stored_rows(&path)?.iter().all(|(_, value)| !contains(value, b"note")));
+    db.rotate_encryption_key(new_key.clone())?;
+    // The open handle switches to the new key.
+    let notes = db.get_collection::<u32, String>("notes")?;
+    assert_eq!(notes.read()?.get(2u32)?, Some("second note".to_string()));
+  }
+
+  let stale = Database::builder(&path).encryption_key(old_key).open();
+  assert!(matches!(stale, Err(Error::WrongEncryptionKey)));
+  let db = Database::builder(&path).encryption_key(new_key).open()?;
+  let notes = db.get_collection::<u32, String>("notes")?;
+  assert_eq!(notes.read()?.iter_values().collect::<Result<Vec<_>, _>>()?, vec!["first note", "second note"]);
+  Ok(())
+}
+
+#[test]
+fn test_encrypted_keys_support_lookups() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("enc.db");
+  let db = Database::builder(&path).encryption_key(EncryptionKey::generate()).encrypt_keys(true).open()?;
+  let emails = db.get_collection::<String, u32>("emails")?;
+  emails.transaction(|tx| {
+    for (i, name) in ["carol", "alice", "bob"].into_iter().enumerate() {
+      tx.set(format!("{name}@example.com"), i as u32)?;
+    }
+    Ok(())
+  })?;
+  assert!(stored_rows(&path)?.iter().all(|(key, _)| !contains(key, b"example.com")));
+
+  let mut tx = emails.begin()?;
+  assert_eq!(tx.get("bob@example.com")?, Some(2));
+  assert!(tx.contains("alice@example.com")?);
+  tx.del("carol@example.com")?;
+  let mut keys = tx.keys()?;
+  keys.sort();
+  assert_eq!(keys, vec!["alice@example.com".to_string(), "bob@example.com".to_string()]);
+  assert_eq!(tx.page(None, 1)?.entries.len(), 1);
+  assert!(matches!(tx.range("a".to_string().."c".to_string()), Err(Error::KeyOrderUnavailable)));
+  assert!(matches!(tx.scan_prefix("alice"), Err(Error::KeyOrderUnavailable)));
+  tx.commit()?;
+  Ok(())
+}
+
+#[test]
+fn test_index_keys_are_encrypted_at_rest() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("enc.db");
+  let db = Database::builder(&path).encryption_key(EncryptionKey::generate()).open()?;
+  let people = db.get_collection::<u32, String>("people")?;
+  people.transaction(|tx| tx.set(1u32, "alice@example.com"))?;
+  people.create_unique_index("by_email", |email: &String| email.clone())?;
+  people.transaction(|tx| tx.set(2u32, "bob@example.com"))?;
+
+  let conn = rusqlite::Connection::open(&path)?;
+  let index_keys: Vec<Vec<u8>> = conn.prepare("SELECT index_key FROM kv_index")?
+    .query_map([], |row| row.get(0))?
+    .collect::<Result<_, _>>()?;
+  assert_eq!(index_keys.len(), 2);
+  assert!(index_keys.iter().all(|key| !contains(key, b"example.com")));
+
+  let tx = people.read()?;
+  assert_eq!(tx.get_by_index("by_email", "bob@example.com")?, vec![(2, "bob@example.com".to_string())]);
+  assert_eq!(tx.range_by_index::<String, _>("by_email", ..)?.len(), 2);
+  let range = tx.range_by_index("by_email", "a".to_string().."c".to_string());
+  assert!(matches!(range, Err(Error::KeyOrderUnavailable)));
+  drop(tx);
+  let err = people.transaction(|tx| tx.set(3u32, "alice@example.com")).unwrap_err();
+  assert_eq!(err.conflicting_key::<u32>(), Some(1));
+  Ok(())
+}
+
+#[test]
+fn test_other_handles_fail_after_encryption_changes() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("enc.db");
+  let plain = Database::new(&path)?.get_collection::<u32, String>("notes")?;
+  plain.transaction(|tx| tx.set(1u32, "first note"))?;
+
+  let key = EncryptionKey::generate();
+  let first = Database::builder(&path).encryption_key(key.clone()).open()?;
+  assert!(matches!(plain.transaction(|tx| tx.set(2u32, "plain note")), Err(Error::EncryptionKeyRequired)));
+
+  let second = Database::builder(&path).encryption_key(key).open()?.get_collection::<u32, String>("notes")?;
+  first.rotate_encryption_key(EncryptionKey::generate())?;
+  assert!(matches!(second.transaction(|tx| tx.set(2u32, "stale note")), Err(Error::WrongEncryptionKey)));
+  assert!(matches!(second.read(), Err(Error::WrongEncryptionKey)));
+
+  let notes = first.get_collection::<u32, String>("notes")?;
+  notes.transaction(|tx| tx.set(2u32, "second note"))?;
+  assert_eq!(notes.read()?.iter_values().collect::<Result<Vec<_>, _>>()?, vec!["first note", "second note"]);
+  Ok(())
+}
+
+#[test]
+fn test_key_rotation_rewrites_entries_in_batches() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("enc.db");
+  let new_key = EncryptionKey::generate();
+  {
+    let db = Database::builder(&path).encryption_key(EncryptionKey::generate()).encrypt_keys(true).open()?;
+    let items = db.get_collection::<u32, String>("items")?;
+    items.create_index("by_name", |name: &String| name.clone())?;
+    items.transaction(|tx| tx.set_many((0..1500u32).map(|i| (i, format!("item {i}")))))?;
+    db.rotate_encryption_key(new_key.clone())?;
+  }
+
+  let db = Database::builder(&path).encryption_key(new_key).encrypt_keys(true).open()?;
+  let items = db.collection_builder::<u32, String>("items").index("by_name", |name: &String| name.clone()).open()?;
+  let tx = items.read()?;
+  assert_eq!(tx.count()?, 1500);
+  assert_eq!(tx.get(1234u32)?, Some("item 1234".to_string()));
+  assert_eq!(tx.get_by_index("by_name", "item 777")?, vec![(777, "item 777".to_string())]);
+  Ok(())
+}
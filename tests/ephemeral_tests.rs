@@ -0,0 +1,34 @@
+use storedb::{Database, Error};
+
+#[test]
+fn test_in_memory_databases_are_isolated() -> Result<(), Error> {
+  let first = Database::in_memory()?;
+  let second = Database::in_memory()?;
+  assert!(first.path().is_none());
+
+  first.get_collection::<u32, String>("items")?.transaction(|tx| tx.set(1u32, "one"))?;
+  let items = second.get_collection::<u32, String>("items")?;
+  assert_eq!(items.read()?.count()?, 0);
+
+  let items = first.get_collection::<u32, String>("items")?;
+  assert_eq!(items.read()?.get(1u32)?, Some("one".to_string()));
+  Ok(())
+}
+
+#[test]
+fn test_temporary_database_is_deleted_on_drop() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let path = db.path().unwrap().to_path_buf();
+  let items = db.get_collection::<u32, u32>("items")?;
+  items.transaction(|tx| tx.set(1u32, 1u32))?;
+  assert_eq!(items.read()?.get(1u32)?, Some(1));
+  assert!(path.exists());
+
+  // Collections keep the database alive.
+  drop(db);
+  assert!(path.exists());
+  drop(items);
+  assert!(!path.exists());
+  assert!(!path.parent().unwrap().exists());
+  Ok(())
+}
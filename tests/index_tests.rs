@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use storedb::{Database, Error};
+use tempfile::TempDir;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct User {
+  email: String,
+  age: u32,
+}
+
+fn user(email: &str, age: u32) -> User {
+  User { email: email.to_string(), age }
+}
+
+#[test]
+fn test_index_follows_writes() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let users = db.get_collection::<u32, User>("users")?;
+  users.create_index("by_email", |u: &User| u.email.clone())?;
+  users.create_index("by_age", |u: &User| u.age)?;
+
+  let mut tx = users.begin()?;
+  tx.set(1u32, user("ann@example.com", 30))?;
+  tx.put(2u32, user("bob@example.com", 25))?;
+  tx.set_many([(3u32, user("cid@example.com", 30)), (4, user("dee@example.com", 41))])?;
+  assert_eq!(tx.get_by_index("by_email", "bob@example.com")?, vec![(2, user("bob@example.com", 25))]);
+  assert_eq!(tx.get_by_index("by_age", &30u32)?.len(), 2);
+
+  // Changing a value moves its entry in the index.
+  tx.set(2u32, user("bob@example.org", 25))?;
+  assert!(tx.get_by_index("by_email", "bob@example.com")?.is_empty());
+  assert_eq!(tx.get_by_index("by_email", "bob@example.org")?.len(), 1);
+
+  tx.del(1u32)?;
+  let ages: Vec<u32> = tx.range_by_index("by_age", 26u32..)?.into_iter().map(|(k, _)| k).collect();
+  assert_eq!(ages, vec![3, 4]);
+  assert!(matches!(tx.get_by_index("by_name", "ann"), Err(Error::IndexNotFound(_))));
+
+  tx.clear()?;
+  assert!(tx.range_by_index::<u32, _>("by_age", ..)?.is_empty());
+  tx.commit()?;
+  Ok(())
+}
+
+#[test]
+fn test_index_definitions_persist() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("indexed.db");
+  {
+    let db = Database::new(&path)?;
+    let users = db.get_collection::<u32, User>("users")?;
+    users.transaction(|tx| tx.set_many([(1u32, user("ann@example.com", 30)), (2, user("bob@example.com", 25))]))?;
+    // Existing entries are indexed when the index is created.
+    users.create_index("by_email", |u: &User| u.email.clone())?;
+    db.copy_collection("users", "users_copy")?;
+  }
+
+  // Writable handles must register the index before opening the collection.
+  let db = Database::new(&path)?;
+  let err = db.get_collection::<u32, User>("users").unwrap_err();
+  assert!(matches!(err, Error::IndexNotRegistered(name) if name == "by_email"));
+  let reader = Database::builder(&path).read_only(true).open()?;
+  assert_eq!(reader.get_collection::<u32, User>("users")?.read()?.get_by_index("by_email", "ann@example.com")?.len(), 1);
+
+  let users = db.collection_builder::<u32, User>("users").index("by_email", |u: &User| u.email.clone()).open()?;
+  users.transaction(|tx| tx.set(3u32, user("cid@example.com", 52)))?;
+  assert_eq!(users.read()?.get_by_index("by_email", "cid@example.com")?.len(), 1);
+
+  let copy = reader.get_collection::<u32, User>("users_copy")?;
+  assert_eq!(copy.read()?.get_by_index("by_email", "bob@example.com")?.len(), 1);
+  // An index that is no longer registered anywhere can still be dropped.
+  db.drop_index("users_copy", "by_email")?;
+  let copy = db.get_collection::<u32, User>("users_copy")?;
+  copy.transaction(|tx| tx.set(3u32, user("cid@example.com", 52)))?;
+  assert!(matches!(copy.read()?.get_by_index("by_email", "bob@example.com"), Err(Error::IndexNotFound(_))));
+  Ok(())
+}
+
+#[test]
+fn test_index_changes_from_other_handles() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("indexed.db");
+  let first = Database::new(&path)?.get_collection::<u32, User>("users")?;
+  let second = Database::new(&path)?.get_collection::<u32, User>("users")?;
+  first.transaction(|tx| tx.set(1u32, user("ann@example.com", 30)))?;
+
+  second.create_index("by_email", |u: &User| u.email.clone())?;
+  let err = first.transaction(|tx| tx.set(2u32, user("bob@example.com", 25))).unwrap_err();
+  assert!(matches!(err, Error::IndexNotRegistered(name) if name == "by_email"));
+  first.create_index("by_email", |u: &User| u.email.clone())?;
+  first.transaction(|tx| tx.set(2u32, user("bob@example.com", 25)))?;
+  assert_eq!(second.read()?.get_by_index("by_email", "bob@example.com")?.len(), 1);
+
+  // Once dropped, an index must be registered again after it is recreated.
+  second.drop_index("by_email")?;
+  first.transaction(|tx| tx.set(3u32, user("cid@example.com", 52)))?;
+  second.create_index("by_email", |u: &User| u.email.clone())?;
+  assert!(first.transaction(|tx| tx.set(3u32, user("cid@example.org", 52))).is_err());
+  assert_eq!(first.read()?.get_by_index("by_email", "cid@example.com")?.len(), 1);
+  Ok(())
+}
+
+#[test]
+fn test_index_created_while_other_threads_write() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let users = db.get_collection::<u32, User>("users")?;
+  let writer = {
+    let users = users.clone();
+    std::thread::spawn(move || -> Result<(), Error> {
+      for i in 0..200u32 {
+        users.transaction(|tx| tx.set(i, user(&format!("user{i}@example.com"), i)))?;
+      }
+      Ok(())
+    })
+  };
+  users.create_index("by_age", |u: &User| u.age)?;
+  writer.join().unwrap()?;
+  assert_eq!(users.read()?.get_by_index("by_age", &199u32)?.len(), 1);
+  Ok(())
+}
@@ -0,0 +1,51 @@
+use storedb::{Database, Error};
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_iter_spans_multiple_batches() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, String>("big")?;
+
+  let mut tx = coll.begin()?;
+  for k in (0..1500u32).rev() {
+    tx.set(k, format!("v{}", k))?;
+  }
+  tx.commit()?;
+
+  let tx = coll.begin()?;
+  let mut expected = 0u32;
+  for entry in tx.iter() {
+    let (k, v) = entry?;
+    assert_eq!(k, expected);
+    assert_eq!(v, format!("v{}", k));
+    expected += 1;
+  }
+  assert_eq!(expected, 1500);
+
+  let keys: Vec<u32> = tx.iter_keys().collect::<Result<_, _>>()?;
+  assert_eq!(keys, (0..1500).collect::<Vec<_>>());
+  Ok(())
+}
+
+#[test]
+fn test_iter_values_and_early_stop() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<String, u64>("vals")?;
+
+  let mut tx = coll.begin()?;
+  tx.set("c", 3u64)?;
+  tx.set("a", 1u64)?;
+  tx.set("b", 2u64)?;
+  tx.commit()?;
+
+  let tx = coll.begin()?;
+  let values: Vec<u64> = tx.iter_values().collect::<Result<_, _>>()?;
+  assert_eq!(values, vec![1, 2, 3]);
+
+  let first = tx.iter().next().transpose()?;
+  assert_eq!(first, Some(("a".to_string(), 1)));
+  assert_eq!(tx.iter().count(), 3);
+  Ok(())
+}
@@ -0,0 +1,80 @@
+use storedb::{CollectionInfo, Database, Error};
+
+#[test]
+fn test_list_and_drop_collections() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let users = db.get_collection::<u32, String>("users")?;
+  users.transaction(|tx| tx.set(1u32, "alice"))?;
+  db.get_collection::<String, u64>("accounts")?;
+
+  let collections = db.list_collections()?;
+  assert_eq!(collections, vec![
+    CollectionInfo { name: "accounts".into(), key_type: "alloc::string::String".into(), value_type: "u64".into(), version: 0, codec: "postcard".into(), compression: "none".into() },
+    CollectionInfo { name: "users".into(), key_type: "u32".into(), value_type: "alloc::string::String".into(), version: 0, codec: "postcard".into(), compression: "none".into() },
+  ]);
+
+  db.drop_collection("users")?;
+  assert_eq!(db.list_collections()?.len(), 1);
+  assert!(matches!(db.drop_collection("users"), Err(Error::CollectionNotFound(_))));
+
+  // The name is free again, with no leftover entries.
+  let users = db.get_collection::<String, String>("users")?;
+  assert_eq!(users.read()?.count()?, 0);
+  Ok(())
+}
+
+#[test]
+fn test_rename_and_copy_collections() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let items = db.get_collection::<u32, String>("items")?;
+  items.transaction(|tx| {
+    tx.set(1u32, "one")?;
+    tx.set(2u32, "two")
+  })?;
+
+  db.copy_collection("items", "backup")?;
+  db.rename_collection("items", "renamed")?;
+  assert!(matches!(db.rename_collection("renamed", "backup"), Err(Error::CollectionExists(_))));
+  assert!(matches!(db.copy_collection("items", "other"), Err(Error::CollectionNotFound(_))));
+
+  let names: Vec<String> = db.list_collections()?.into_iter().map(|c| c.name).collect();
+  assert_eq!(names, vec!["backup", "renamed"]);
+
+  let renamed = db.get_collection::<u32, String>("renamed")?;
+  let backup = db.get_collection::<u32, String>("backup")?;
+  backup.transaction(|tx| tx.del(1u32))?;
+  assert_eq!(renamed.read()?.keys()?, vec![1, 2]);
+  assert_eq!(backup.read()?.keys()?, vec![2]);
+  Ok(())
+}
+
+#[test]
+fn test_write_through_handle_of_dropped_collection() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let users = db.get_collection::<u32, String>("users")?;
+  users.transaction(|tx| tx.set(1u32, "alice"))?;
+
+  db.drop_collection("users")?;
+  assert!(matches!(users.transaction(|tx| tx.set(2u32, "bob")), Err(Error::CollectionNotFound(_))));
+  assert!(matches!(users.read(), Err(Error::CollectionNotFound(_))));
+
+  // Nothing was left behind for a new collection of the same name to decode.
+  let users = db.get_collection::<u32, u64>("users")?;
+  assert_eq!(users.read()?.scan()?, vec![]);
+  Ok(())
+}
+
+#[test]
+fn test_write_through_handle_of_renamed_collection() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let items = db.get_collection::<u32, String>("items")?;
+  items.transaction(|tx| tx.set(1u32, "one"))?;
+
+  db.rename_collection("items", "renamed")?;
+  assert!(matches!(items.transaction(|tx| tx.set(2u32, "two")), Err(Error::CollectionNotFound(_))));
+  assert_eq!(db.list_collections()?.into_iter().map(|c| c.name).collect::<Vec<_>>(), vec!["renamed"]);
+
+  let renamed = db.get_collection::<u32, String>("renamed")?;
+  assert_eq!(renamed.read()?.keys()?, vec![1]);
+  Ok(())
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use storedb::{Database, Error};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct UserV0 {
+  name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct UserV1 {
+  name: String,
+  admin: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct UserV2 {
+  first: String,
+  last: String,
+  admin: bool,
+}
+
+fn migrate(db: &Database) -> Result<(), Error> {
+  if db.collection_version("users")? < 1 {
+    db.migrate_collection::<u32, UserV0, u32, UserV1>("users", |(id, old)| {
+      (id, UserV1 { name: old.name, admin: id == 1 })
+    })?;
+  }
+  if db.collection_version("users")? < 2 {
+    db.migrate_collection::<u32, UserV1, String, UserV2>("users", |(id, old)| {
+      let (first, last) = old.name.split_once(' ').unwrap();
+      (format!("user-{id}"), UserV2 { first: first.into(), last: last.into(), admin: old.admin })
+    })?;
+  }
+  Ok(())
+}
+
+#[test]
+fn test_chained_migrations() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let users = db.get_collection::<u32, UserV0>("users")?;
+  users.transaction(|tx| {
+    tx.set(1u32, UserV0 { name: "Ada Lovelace".into() })?;
+    tx.set(2u32, UserV0 { name: "Alan Turing".into() })
+  })?;
+  assert_eq!(db.collection_version("users")?, 0);
+
+  migrate(&db)?;
+  assert_eq!(db.collection_version("users")?, 2);
+  assert!(matches!(db.get_collection::<u32, UserV0>("users"), Err(Error::TypeMismatch { .. })));
+
+  let users = db.get_collection::<String, UserV2>("users")?;
+  assert_eq!(users.read()?.scan()?, vec![
+    ("user-1".to_string(), UserV2 { first: "Ada".into(), last: "Lovelace".into(), admin: true }),
+    ("user-2".to_string(), UserV2 { first: "Alan".into(), last: "Turing".into(), admin: false }),
+  ]);
+
+  // Running the chain again is a no-op.
+  migrate(&db)?;
+  assert_eq!(db.collection_version("users")?, 2);
+  Ok(())
+}
+
+#[test]
+fn test_failed_migration_rolls_back() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let users = db.get_collection::<u32, UserV0>("users")?;
+  users.transaction(|tx| {
+    tx.set(1u32, UserV0 { name: "a".into() })?;
+    tx.set(2u32, UserV0 { name: "b".into() })
+  })?;
+
+  // Both entries map to the same new key.
+  let result = db.migrate_collection::<u32, UserV0, u8, UserV0>("users", |(_, old)| (0, old));
+  assert!(matches!(result, Err(Error::KeyAlreadyExists)));
+  assert!(matches!(
+    db.migrate_collection::<u64, UserV0, u64, UserV0>("users", |entry| entry),
+    Err(Error::TypeMismatch { .. })
+  ));
+  assert!(matches!(
+    db.migrate_collection::<u32, UserV0, u32, UserV0>("missing", |entry| entry),
+    Err(Error::CollectionNotFound(_))
+  ));
+
+  assert_eq!(db.collection_version("users")?, 0);
+  assert_eq!(users.read()?.count()?, 2);
+  Ok(())
+}
@@ -0,0 +1,73 @@
+use std::time::Duration;
+use storedb::{Database, Error, JournalMode, Synchronous};
+use tempfile::TempDir;
+
+#[test]
+fn test_builder_applies_settings() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("opts.db");
+  let db = Database::builder(&path)
+    .journal_mode(JournalMode::Delete)
+    .synchronous(Synchronous::Normal)
+    .busy_timeout(Duration::from_millis(250))
+    .cache_size(-4096)
+    .page_size(8192)
+    .mmap_size(1 << 20)
+    .reader_pool_size(2)
+    .open()?;
+  let coll = db.get_collection::<u32, String>("items")?;
+  coll.transaction(|tx| tx.set(1u32, "one"))?;
+  assert_eq!(coll.read()?.get(1u32)?, Some("one".to_string()));
+
+  let conn = rusqlite::Connection::open(&path)?;
+  let page_size: u32 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+  let journal_mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
+  assert_eq!(page_size, 8192);
+  assert_eq!(journal_mode, "delete");
+  Ok(())
+}
+
+#[test]
+fn test_read_only_and_create_if_missing() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("missing.db");
+  assert!(Database::builder(&path).create_if_missing(false).open().is_err());
+  assert!(!path.exists());
+
+  {
+    let db = Database::new(&path)?;
+    db.get_collection::<u32, u32>("items")?.transaction(|tx| tx.set(1u32, 10u32))?;
+  }
+
+  let db = Database::builder(&path).read_only(true).open()?;
+  let coll = db.get_collection::<u32, u32>("items")?;
+  assert_eq!(coll.read()?.get(1u32)?, Some(10));
+  assert!(coll.transaction(|tx| tx.set(2u32, 20u32)).is_err());
+  assert!(db.get_collection::<u32, u32>("new_items").is_err());
+  Ok(())
+}
+
+#[test]
+fn test_read_only_open_requires_upgraded_database() -> Result<(), Error> {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("legacy.db");
+  {
+    let conn = rusqlite::Connection::open(&path)?;
+    conn.execute_batch(r#"
+      CREATE TABLE collection_meta (name TEXT PRIMARY KEY, key_type TEXT NOT NULL, value_type TEXT NOT NULL);
+      CREATE TABLE kv_store (collection TEXT, key BLOB, value BLOB NOT NULL, PRIMARY KEY(collection, key));
+      INSERT INTO collection_meta (name, key_type, value_type) VALUES ('legacy', 'u32', 'u32');
+    "#)?;
+  }
+  assert!(matches!(Database::builder(&path).read_only(true).open(), Err(Error::UpgradeRequired)));
+
+  // Upgrading the schema leaves the keys of each collection to be rewritten
+  // when it is first opened.
+  drop(Database::new(&path)?);
+  let db = Database::builder(&path).read_only(true).open()?;
+  assert!(matches!(db.get_collection::<u32, u32>("legacy"), Err(Error::UpgradeRequired)));
+
+  Database::new(&path)?.get_collection::<u32, u32>("legacy")?;
+  assert_eq!(db.get_collection::<u32, u32>("legacy")?.read()?.count()?, 0);
+  Ok(())
+}
@@ -0,0 +1,79 @@
+use storedb::{Database, Error, PageCursor};
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_page_through_collection() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, String>("listing")?;
+
+  let mut tx = coll.begin()?;
+  for k in 0..25u32 {
+    tx.set(k, k.to_string())?;
+  }
+  tx.commit()?;
+
+  let mut seen = Vec::new();
+  let mut cursor: Option<String> = None;
+  loop {
+    // Every page runs in its own transaction and resumes from a string cursor.
+    let tx = coll.begin()?;
+    let page = match &cursor {
+      Some(c) => tx.page_after(&c.parse::<PageCursor>()?, 10)?,
+      None => tx.page(None, 10)?,
+    };
+    assert!(page.entries.len() <= 10);
+    seen.extend(page.entries.into_iter().map(|(k, _)| k));
+    match page.next {
+      Some(next) => cursor = Some(next.to_string()),
+      None => break,
+    }
+  }
+  assert_eq!(seen, (0..25).collect::<Vec<_>>());
+  Ok(())
+}
+
+#[test]
+fn test_page_after_key() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, u32>("listing")?;
+
+  let mut tx = coll.begin()?;
+  for k in 0..5u32 {
+    tx.set(k, k)?;
+  }
+  tx.commit()?;
+
+  let tx = coll.begin()?;
+  let page = tx.page(Some(2), 2)?;
+  assert_eq!(page.entries, vec![(3, 3), (4, 4)]);
+  assert!(page.next.is_none());
+
+  let page = tx.page(Some(0), 2)?;
+  assert_eq!(page.entries, vec![(1, 1), (2, 2)]);
+  assert!(page.next.is_some());
+
+  assert!(matches!("xyz".parse::<PageCursor>(), Err(Error::InvalidCursor)));
+  Ok(())
+}
+
+#[test]
+fn test_page_limits() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, u32>("listing")?;
+  coll.transaction(|tx| tx.set_many((0..600u32).map(|k| (k, k))))?;
+
+  let tx = coll.read()?;
+  let page = tx.page(None, usize::MAX)?;
+  assert_eq!(page.entries.len(), 600);
+  assert!(page.next.is_none());
+
+  // An empty page would end a listing that has entries left.
+  assert!(matches!(tx.page(None, 0), Err(Error::InvalidPageLimit)));
+  let cursor = tx.page(None, 1)?.next.unwrap();
+  assert!(matches!(tx.page_after(&cursor, 0), Err(Error::InvalidPageLimit)));
+  assert_eq!(tx.page_after(&cursor, usize::MAX)?.entries.len(), 599);
+  Ok(())
+}
@@ -0,0 +1,49 @@
+use storedb::{Database, Error};
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_scan_prefix_string_keys() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<String, u32>("orders")?;
+
+  let mut tx = coll.begin()?;
+  tx.set("tenant/123/order/9", 9u32)?;
+  tx.set("tenant/123/order/10", 10u32)?;
+  tx.set("tenant/1234/order/1", 1u32)?;
+  tx.set("tenant/12", 12u32)?;
+  tx.set("tenant/124/order/1", 2u32)?;
+  tx.commit()?;
+
+  let tx = coll.begin()?;
+  let entries = tx.scan_prefix("tenant/123/")?;
+  assert_eq!(entries, vec![
+    ("tenant/123/order/10".to_string(), 10),
+    ("tenant/123/order/9".to_string(), 9),
+  ]);
+  assert_eq!(tx.keys_prefix("tenant/123")?.len(), 3);
+  assert_eq!(tx.keys_prefix("")?.len(), 5);
+  assert!(tx.scan_prefix("tenant/9")?.is_empty());
+  Ok(())
+}
+
+#[test]
+fn test_scan_prefix_tuple_keys() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<(u64, u64), String>("pairs")?;
+
+  let mut tx = coll.begin()?;
+  for a in [1u64, 2, 255, 256] {
+    for b in [3u64, 1, 2] {
+      tx.set((a, b), format!("{}-{}", a, b))?;
+    }
+  }
+  tx.commit()?;
+
+  let tx = coll.begin()?;
+  assert_eq!(tx.keys_prefix(&(255u64,))?, vec![(255, 1), (255, 2), (255, 3)]);
+  let values: Vec<String> = tx.iter_prefix(&(2u64,))?.map(|e| e.map(|(_, v)| v)).collect::<Result<_, _>>()?;
+  assert_eq!(values, vec!["2-1", "2-2", "2-3"]);
+  Ok(())
+}
@@ -0,0 +1,106 @@
+use storedb::{Database, Error};
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_scan_returns_keys_in_order() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<i64, String>("ordered")?;
+
+  let mut tx = coll.begin()?;
+  for k in [128i64, 2, -5, 70000, 0, -300] {
+    tx.set(k, k.to_string())?;
+  }
+  tx.commit()?;
+
+  let tx = coll.begin()?;
+  assert_eq!(tx.keys()?, vec![-300, -5, 0, 2, 128, 70000]);
+  let rev: Vec<i64> = tx.scan_rev()?.into_iter().map(|(k, _)| k).collect();
+  assert_eq!(rev, vec![70000, 128, 2, 0, -5, -300]);
+  Ok(())
+}
+
+#[test]
+fn test_range_variants() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, u32>("ranges")?;
+
+  let mut tx = coll.begin()?;
+  for k in 0..300u32 {
+    tx.set(k, k * 2)?;
+  }
+  tx.commit()?;
+
+  let tx = coll.begin()?;
+  let keys = |entries: Vec<(u32, u32)>| entries.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+  assert_eq!(keys(tx.range(126..130)?), vec![126, 127, 128, 129]);
+  assert_eq!(keys(tx.range(126..=128)?), vec![126, 127, 128]);
+  assert_eq!(keys(tx.range_rev(126..130)?), vec![129, 128, 127, 126]);
+  assert_eq!(keys(tx.range_from(297u32)?), vec![297, 298, 299]);
+  assert_eq!(keys(tx.range_to(3u32)?), vec![0, 1, 2]);
+  assert_eq!(tx.range(5..5)?, vec![]);
+  assert_eq!(tx.range(128..129)?, vec![(128, 256)]);
+  Ok(())
+}
+
+#[test]
+fn test_string_and_tuple_key_order() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+
+  let strings = db.get_collection::<String, ()>("strings")?;
+  let mut tx = strings.begin()?;
+  for k in ["b", "a\0", "ab", "a", ""] {
+    tx.set(k, ())?;
+  }
+  tx.commit()?;
+  let tx = strings.begin()?;
+  assert_eq!(tx.keys()?, vec!["", "a", "a\0", "ab", "b"]);
+  drop(tx);
+
+  let tuples = db.get_collection::<(u64, String), ()>("tuples")?;
+  let mut tx = tuples.begin()?;
+  for k in [(2u64, "a"), (1, "zz"), (1, "b"), (300, "")] {
+    tx.set((k.0, k.1.to_string()), ())?;
+  }
+  tx.commit()?;
+  let tx = tuples.begin()?;
+  let expected: Vec<(u64, String)> = vec![(1, "b".into()), (1, "zz".into()), (2, "a".into()), (300, "".into())];
+  assert_eq!(tx.keys()?, expected);
+  Ok(())
+}
+
+#[test]
+fn test_legacy_postcard_keys_are_migrated() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+
+  // Lay out a database the way storedb 2.0 wrote it.
+  {
+    let conn = rusqlite::Connection::open(temp_file.path())?;
+    conn.execute_batch(r#"
+      CREATE TABLE collection_meta (name TEXT PRIMARY KEY, key_type TEXT NOT NULL, value_type TEXT NOT NULL);
+      CREATE TABLE kv_store (collection TEXT, key BLOB, value BLOB NOT NULL, PRIMARY KEY(collection, key));
+    "#)?;
+    conn.execute(
+      "INSERT INTO collection_meta (name, key_type, value_type) VALUES ('legacy', 'u32', 'alloc::string::String')",
+      [],
+    )?;
+    // Enough entries to be rewritten in several batches.
+    for k in [2u32, 128, 1].into_iter().chain(1000..1600) {
+      conn.execute(
+        "INSERT INTO kv_store (collection, key, value) VALUES ('legacy', ?, ?)",
+        rusqlite::params![postcard::to_stdvec(&k)?, postcard::to_stdvec(&k.to_string())?],
+      )?;
+    }
+  }
+
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, String>("legacy")?;
+  let tx = coll.begin()?;
+  assert_eq!(tx.keys()?[..4], [1, 2, 128, 1000]);
+  assert_eq!(tx.count()?, 603);
+  assert_eq!(tx.get(128u32)?, Some("128".to_string()));
+  assert_eq!(tx.get(1599u32)?, Some("1599".to_string()));
+  Ok(())
+}
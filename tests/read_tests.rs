@@ -0,0 +1,37 @@
+use storedb::{Database, Error};
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_concurrent_read_transactions() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, String>("items")?;
+
+  let writer = db.get_collection::<u32, String>("items")?;
+  writer.transaction(|tx| {
+    tx.set(1u32, "one")?;
+    tx.set(2u32, "two")
+  })?;
+
+  let first = coll.read()?;
+  let second = coll.read()?;
+  assert_eq!(first.get(1u32)?, Some("one".to_string()));
+  assert_eq!(second.count()?, 2);
+  assert_eq!(first.keys()?, second.keys()?);
+  Ok(())
+}
+
+#[test]
+fn test_read_on_in_memory_database() -> Result<(), Error> {
+  let db = Database::new(":memory:")?;
+  let coll = db.get_collection::<u32, u32>("items")?;
+  coll.transaction(|tx| tx.set(7u32, 49u32))?;
+
+  let tx = coll.read()?;
+  assert_eq!(tx.get(7u32)?, Some(49));
+  // Reads share the writer connection, so only one can be open per thread.
+  assert!(matches!(coll.read(), Err(Error::NestedTransaction)));
+  drop(tx);
+  assert_eq!(coll.read()?.get(7u32)?, Some(49));
+  Ok(())
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use storedb::{Database, Error};
+
+mod v1 {
+  use super::*;
+
+  #[derive(Serialize, Deserialize, Debug, PartialEq)]
+  pub enum Role {
+    Guest,
+    Member { since: u32 },
+  }
+
+  #[derive(Serialize, Deserialize, Debug, PartialEq)]
+  pub struct User {
+    pub name: String,
+    pub role: Role,
+    pub friends: Vec<Tree>,
+  }
+
+  #[derive(Serialize, Deserialize, Debug, PartialEq)]
+  pub enum Tree {
+    Leaf(u32),
+    Node(Vec<Tree>),
+  }
+}
+
+mod moved {
+  use super::*;
+
+  #[derive(Serialize, Deserialize, Debug, PartialEq)]
+  pub enum Role {
+    Guest,
+    Member { since: u32 },
+  }
+
+  #[derive(Serialize, Deserialize, Debug, PartialEq)]
+  pub struct User {
+    pub name: String,
+    pub role: Role,
+    pub friends: Vec<Tree>,
+  }
+
+  #[derive(Serialize, Deserialize, Debug, PartialEq)]
+  pub enum Tree {
+    Leaf(u32),
+    Node(Vec<Tree>),
+  }
+}
+
+mod changed {
+  use super::*;
+
+  #[derive(Serialize, Deserialize, Debug, PartialEq)]
+  pub enum Role {
+    Guest,
+    Member { since: u64 },
+  }
+
+  #[derive(Serialize, Deserialize, Debug, PartialEq)]
+  pub struct User {
+    pub name: String,
+    pub role: Role,
+    pub friends: Vec<super::v1::Tree>,
+  }
+}
+
+#[test]
+fn test_moved_type_keeps_schema() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let users = db.get_collection::<String, v1::User>("users")?;
+  users.transaction(|tx| tx.set("bob", v1::User {
+    name: "Bob".into(),
+    role: v1::Role::Member { since: 2020 },
+    friends: vec![v1::Tree::Node(vec![v1::Tree::Leaf(1)])],
+  }))?;
+
+  let users = db.get_collection::<String, moved::User>("users")?;
+  let bob = users.read()?.get("bob")?.unwrap();
+  assert_eq!(bob.role, moved::Role::Member { since: 2020 });
+  assert_eq!(bob.friends, vec![moved::Tree::Node(vec![moved::Tree::Leaf(1)])]);
+
+  // The stored type names follow the type to its new location.
+  let info = db.list_collections()?.pop().unwrap();
+  assert_eq!(info.value_type, std::any::type_name::<moved::User>());
+  Ok(())
+}
+
+#[test]
+fn test_changed_shape_is_a_mismatch() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  db.get_collection::<String, v1::User>("users")?;
+  // A field nested inside an enum variant changed type.
+  assert!(matches!(db.get_collection::<String, changed::User>("users"), Err(Error::TypeMismatch { .. })));
+  assert!(matches!(db.get_collection::<u64, v1::User>("users"), Err(Error::TypeMismatch { .. })));
+  db.get_collection::<String, moved::User>("users")?;
+  Ok(())
+}
+
+#[test]
+fn test_collections_without_fingerprints_are_upgraded() -> Result<(), Error> {
+  let dir = tempfile::TempDir::new().unwrap();
+  let path = dir.path().join("legacy.db");
+  Database::new(&path)?.get_collection::<u32, v1::Role>("roles")?;
+  {
+    let conn = rusqlite::Connection::open(&path)?;
+    conn.execute("UPDATE collection_meta SET key_schema = NULL, value_schema = NULL", [])?;
+  }
+
+  // Without a stored fingerprint the type names must match.
+  let db = Database::new(&path)?;
+  assert!(matches!(db.get_collection::<u32, moved::Role>("roles"), Err(Error::TypeMismatch { .. })));
+  db.get_collection::<u32, v1::Role>("roles")?;
+  db.get_collection::<u32, moved::Role>("roles")?;
+  Ok(())
+}
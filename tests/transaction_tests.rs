@@ -0,0 +1,60 @@
+use std::time::Duration;
+use storedb::{Database, Error, RetryPolicy};
+use tempfile::NamedTempFile;
+
+fn busy() -> Error {
+  Error::SqliteError(rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY), None))
+}
+
+#[test]
+fn test_transaction_commits_on_ok_and_rolls_back_on_err() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let coll = db.get_collection::<u32, String>("items")?;
+
+  let n = coll.transaction(|tx| {
+    tx.set(1u32, "one")?;
+    tx.set(2u32, "two")?;
+    tx.count()
+  })?;
+  assert_eq!(n, 2);
+
+  let result: Result<(), Error> = coll.transaction(|tx| {
+    tx.set(3u32, "three")?;
+    tx.put(1u32, "uno")
+  });
+  assert!(matches!(result, Err(Error::KeyAlreadyExists)));
+  assert_eq!(coll.begin()?.keys()?, vec![1, 2]);
+  Ok(())
+}
+
+#[test]
+fn test_transaction_retries_busy_errors() -> Result<(), Error> {
+  let temp_file = NamedTempFile::new().unwrap();
+  let db = Database::new(temp_file.path())?;
+  let policy = RetryPolicy {
+    max_retries: 3,
+    initial_backoff: Duration::from_millis(1),
+    max_backoff: Duration::from_millis(2),
+  };
+
+  let mut attempts = 0u32;
+  db.transaction_with(&policy, |tx| {
+    attempts += 1;
+    tx.collection::<u32, u32>("counters")?.set(1u32, attempts)?;
+    if attempts < 3 { Err(busy()) } else { Ok(()) }
+  })?;
+  assert_eq!(attempts, 3);
+
+  let coll = db.get_collection::<u32, u32>("counters")?;
+  assert_eq!(coll.begin()?.get(1u32)?, Some(3));
+
+  let mut attempts = 0;
+  let result: Result<(), Error> = coll.transaction_with(&RetryPolicy::none(), |_| {
+    attempts += 1;
+    Err(busy())
+  });
+  assert!(result.unwrap_err().is_busy());
+  assert_eq!(attempts, 1);
+  Ok(())
+}
@@ -0,0 +1,80 @@
+use std::thread;
+use std::time::Duration;
+use storedb::{Database, Error};
+
+const TTL: Duration = Duration::from_millis(50);
+
+#[test]
+fn test_expired_entries_are_hidden() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let sessions = db.get_collection::<String, u32>("sessions")?;
+  sessions.transaction(|tx| {
+    tx.set_with_ttl("a", 1u32, TTL)?;
+    tx.set_with_ttl("b", 2u32, Duration::from_secs(3600))?;
+    tx.set("c", 3u32)?;
+    assert!(tx.expire("c", TTL)?);
+    assert!(!tx.expire("d", TTL)?);
+    Ok(())
+  })?;
+  assert_eq!(sessions.read()?.count()?, 3);
+  thread::sleep(TTL * 2);
+
+  let tx = sessions.read()?;
+  assert_eq!(tx.get("a")?, None);
+  assert!(!tx.contains("c")?);
+  assert_eq!(tx.count()?, 1);
+  assert_eq!(tx.scan()?, vec![("b".to_string(), 2)]);
+  assert_eq!(tx.get_many(&["a".to_string(), "b".to_string()])?, vec![None, Some(2)]);
+  drop(tx);
+
+  // Writes treat expired entries as absent, and clear the expiry.
+  let mut tx = sessions.begin()?;
+  tx.put("a", 10u32)?;
+  assert_eq!(tx.remove("c")?, None);
+  tx.commit()?;
+  thread::sleep(TTL * 2);
+  assert_eq!(sessions.read()?.get("a")?, Some(10));
+  Ok(())
+}
+
+#[test]
+fn test_purge_expired() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let cache = db.get_collection::<u32, String>("cache")?;
+  cache.transaction(|tx| {
+    for i in 0..10u32 {
+      tx.set_with_ttl(i, i.to_string(), if i < 6 { TTL } else { Duration::from_secs(3600) })?;
+    }
+    Ok(())
+  })?;
+  thread::sleep(TTL * 2);
+  assert_eq!(db.purge_expired()?, 6);
+  assert_eq!(db.purge_expired()?, 0);
+  assert_eq!(cache.read()?.count()?, 4);
+
+  cache.transaction(|tx| tx.set_with_ttl(20u32, "soon", TTL))?;
+  let _sweeper = db.start_expiry_sweeper(TTL)?;
+  thread::sleep(TTL * 6);
+  assert_eq!(db.purge_expired()?, 0);
+  Ok(())
+}
+
+#[test]
+fn test_in_place_writes_keep_expiry() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let sessions = db.get_collection::<String, u32>("sessions")?;
+  sessions.transaction(|tx| {
+    for key in ["updated", "swapped", "conditional", "reset"] {
+      tx.set_with_ttl(key, 1u32, TTL)?;
+    }
+    tx.update("updated", |n| n.map(|n| n + 1))?;
+    tx.compare_and_swap("swapped", Some(1), Some(2))?;
+    tx.update_if("conditional", |n| n == Some(&1), 2u32)?;
+    tx.set("reset", 2u32)
+  })?;
+  thread::sleep(TTL * 2);
+
+  let tx = sessions.read()?;
+  assert_eq!(tx.keys()?, vec!["reset"]);
+  Ok(())
+}
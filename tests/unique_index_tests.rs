@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+use storedb::{Database, Error};
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct Account {
+  username: String,
+  plan: String,
+}
+
+fn account(username: &str) -> Account {
+  Account { username: username.to_string(), plan: "free".to_string() }
+}
+
+#[test]
+fn test_unique_index_rejects_conflicts() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let accounts = db.get_collection::<u32, Account>("accounts")?;
+  accounts.create_unique_index("by_username", |a: &Account| a.username.clone())?;
+
+  let mut tx = accounts.begin()?;
+  tx.set(1u32, account("ann"))?;
+  let err = tx.set(2u32, account("ann")).unwrap_err();
+  assert!(matches!(&err, Error::UniqueViolation { index, .. } if index == "by_username"));
+  assert_eq!(err.conflicting_key::<u32>(), Some(1));
+  assert!(!tx.contains(2u32)?);
+  assert!(matches!(tx.put(3u32, account("ann")), Err(Error::UniqueViolation { .. })));
+
+  // Rewriting the entry that holds the key is fine.
+  tx.set(1u32, Account { plan: "pro".into(), ..account("ann") })?;
+  assert!(matches!(tx.put_many([(4u32, account("bob")), (5, account("bob"))]), Err(Error::UniqueViolation { .. })));
+  assert_eq!(tx.get_by_index("by_username", "bob")?.len(), 1);
+
+  tx.del(1u32)?;
+  tx.set(2u32, account("ann"))?;
+  tx.commit()?;
+
+  // An expired entry no longer holds its key.
+  accounts.transaction(|tx| tx.set_with_ttl(6u32, account("cid"), Duration::from_millis(20)))?;
+  thread::sleep(Duration::from_millis(50));
+  accounts.transaction(|tx| tx.set(7u32, account("cid")))?;
+  assert_eq!(accounts.read()?.get_by_index("by_username", "cid")?, vec![(7, account("cid"))]);
+  Ok(())
+}
+
+#[test]
+fn test_unique_index_checks_existing_entries() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let accounts = db.get_collection::<u32, Account>("accounts")?;
+  accounts.transaction(|tx| tx.set_many([(1u32, account("ann")), (2, account("bob")), (3, account("ann"))]))?;
+
+  let err = accounts.create_unique_index("by_username", |a: &Account| a.username.clone()).unwrap_err();
+  assert!(matches!(err, Error::UniqueViolation { .. }));
+  assert!(matches!(accounts.read()?.get_by_index("by_username", "ann"), Err(Error::IndexNotFound(_))));
+
+  accounts.transaction(|tx| tx.del(3u32))?;
+  accounts.create_unique_index("by_username", |a: &Account| a.username.clone())?;
+  assert_eq!(accounts.read()?.get_by_index("by_username", "ann")?, vec![(1, account("ann"))]);
+  Ok(())
+}
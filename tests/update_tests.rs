@@ -0,0 +1,83 @@
+use std::thread;
+use std::time::Duration;
+use storedb::{Database, Error};
+
+const TTL: Duration = Duration::from_millis(50);
+
+#[test]
+fn test_update_and_remove() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let tags = db.get_collection::<u32, Vec<String>>("tags")?;
+  let mut tx = tags.begin()?;
+
+  let added = tx.update(1u32, |tags| {
+    let mut tags = tags.unwrap_or_default();
+    tags.push("new".into());
+    Some(tags)
+  })?;
+  assert_eq!(added, Some(vec!["new".to_string()]));
+  assert_eq!(tx.get(1u32)?, added);
+
+  // Returning `None` deletes the entry.
+  assert_eq!(tx.update(1u32, |_| None)?, None);
+  assert!(!tx.contains(1u32)?);
+
+  tx.set(2u32, vec!["old".to_string()])?;
+  assert_eq!(tx.remove(2u32)?, Some(vec!["old".to_string()]));
+  assert_eq!(tx.remove(2u32)?, None);
+  assert_eq!(tx.count()?, 0);
+  tx.commit()?;
+  Ok(())
+}
+
+#[test]
+fn test_get_or_insert_and_upsert() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let counts = db.get_collection::<String, u32>("counts")?;
+  let mut tx = counts.begin()?;
+
+  assert_eq!(tx.get_or_insert_with("a", || 10)?, 10);
+  assert_eq!(tx.get_or_insert_with("a", || unreachable!())?, 10);
+
+  for expected in 1..=3 {
+    assert_eq!(tx.upsert_with("b", || 1, |n| *n += 1)?, expected);
+  }
+  assert_eq!(tx.upsert_with("a", || 0, |n| *n *= 2)?, 20);
+  tx.commit()?;
+
+  assert_eq!(counts.read()?.scan()?, vec![("a".to_string(), 20), ("b".to_string(), 3)]);
+  Ok(())
+}
+
+#[test]
+fn test_updates_of_entries_with_ttl() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let counts = db.get_collection::<String, u32>("counts")?;
+  counts.transaction(|tx| {
+    for key in ["live", "upserted", "updated", "inserted"] {
+      tx.set_with_ttl(key, 1u32, TTL)?;
+    }
+    Ok(())
+  })?;
+
+  // Modifying a live entry keeps its TTL.
+  counts.transaction(|tx| {
+    assert_eq!(tx.update("live", |n| n.map(|n| n + 1))?, Some(2));
+    assert_eq!(tx.upsert_with("live", || unreachable!(), |n| *n += 1)?, 3);
+    Ok(())
+  })?;
+  thread::sleep(TTL * 2);
+  assert!(!counts.read()?.contains("live")?);
+
+  // An expired entry counts as absent, and its TTL is not carried over.
+  counts.transaction(|tx| {
+    assert_eq!(tx.upsert_with("upserted", || 10, |_| unreachable!())?, 10);
+    assert_eq!(tx.update("updated", |n| { assert_eq!(n, None); Some(20) })?, Some(20));
+    assert_eq!(tx.get_or_insert_with("inserted", || 30)?, 30);
+    Ok(())
+  })?;
+  thread::sleep(TTL * 2);
+  let expected = vec![("inserted".to_string(), 30), ("updated".to_string(), 20), ("upserted".to_string(), 10)];
+  assert_eq!(counts.read()?.scan()?, expected);
+  Ok(())
+}
@@ -0,0 +1,60 @@
+use std::sync::mpsc::TryRecvError;
+use storedb::{Change, Database, Error};
+
+#[test]
+fn test_subscribe_sees_committed_changes() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let notes = db.get_collection::<u32, String>("notes")?;
+  let changes = notes.subscribe();
+
+  let mut tx = notes.begin()?;
+  tx.set(1u32, "draft")?;
+  tx.set(1u32, "final")?;
+  tx.put(2u32, "other")?;
+  tx.del(1u32)?;
+  tx.del(3u32)?;
+  assert_eq!(changes.try_recv(), Err(TryRecvError::Empty));
+  tx.commit()?;
+  assert_eq!(changes.try_iter().collect::<Vec<_>>(), vec![
+    Change::Insert { key: 1, value: "draft".to_string() },
+    Change::Update { key: 1, value: "final".to_string() },
+    Change::Insert { key: 2, value: "other".to_string() },
+    Change::Delete { key: 1 },
+  ]);
+
+  let mut tx = notes.begin()?;
+  tx.set(4u32, "discarded")?;
+  tx.rollback()?;
+  assert_eq!(changes.try_recv(), Err(TryRecvError::Empty));
+
+  notes.transaction(|tx| {
+    tx.set_many([(2u32, "edited"), (5, "new")])?;
+    tx.clear()
+  })?;
+  assert_eq!(changes.try_iter().collect::<Vec<_>>(), vec![
+    Change::Update { key: 2, value: "edited".to_string() },
+    Change::Insert { key: 5, value: "new".to_string() },
+    Change::Clear,
+  ]);
+  Ok(())
+}
+
+#[test]
+fn test_watch_one_key() -> Result<(), Error> {
+  let db = Database::temporary()?;
+  let notes = db.get_collection::<u32, String>("notes")?;
+  let watched = notes.watch(1u32)?;
+  drop(notes.subscribe());
+
+  notes.transaction(|tx| tx.set_many([(1u32, "a"), (2, "b")]))?;
+  let tx = db.begin()?;
+  tx.collection::<u32, String>("notes")?.remove(1u32)?;
+  tx.collection::<u32, String>("notes")?.del_many(&[2])?;
+  tx.commit()?;
+
+  assert_eq!(watched.try_iter().collect::<Vec<_>>(), vec![
+    Change::Insert { key: 1, value: "a".to_string() },
+    Change::Delete { key: 1 },
+  ]);
+  Ok(())
+}